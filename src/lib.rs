@@ -0,0 +1,671 @@
+// src/lib.rs
+//! Allele-frequency statistics of groups of samples, as written to INFO by the vcfgrpaf
+//! binary. Genotypes come in already decoded, one [`Gt`] per sample, so the record
+//! reading stays with the caller.
+use anyhow::Context;
+use std::collections::HashMap;
+
+/// Alleles of one sample's genotype, any ploidy; `None` is a missing allele. An index
+/// past the site's alleles, such as 2 at a site with one ALT, is counted as missing by
+/// every statistic, so `0/2` there is the partially missing `0/.`
+pub type Gt = Vec<Option<u32>>;
+
+/// How a call with some alleles missing, such as `./1`, is counted
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HalfMissing {
+    /// Count the whole call as missing: nothing goes to AN, the sample to N_MISS
+    Drop,
+    /// Count the called alleles in AC/AN and the sample in N_PARTIAL
+    #[default]
+    Partial,
+    /// Count the called alleles in AC/AN and the sample in N_HEMI, like haploid calls
+    Hemi,
+    /// Count the called alleles in AC/AN and class the sample by them, e.g. `./1` as HOMALT
+    Keep,
+}
+
+/// Per‑variant statistics of one group ---------------------------------------
+#[derive(Default, Debug, Clone, serde::Serialize)]
+#[non_exhaustive]
+pub struct AfStats {
+    /// Allele counts (REF, ALT1, ALT2, ...)
+    pub ac: Vec<u32>,
+    /// Allele number
+    pub an: u32,
+    /// Haploid calls, and partially missing ones with `HalfMissing::Hemi`
+    pub n_hemi: u32,
+    /// Partially missing calls such as `0/.`, with `HalfMissing::Partial`
+    pub n_partial: u32,
+    /// Calls with only REF alleles
+    pub n_homref: u32,
+    /// Calls with two or more different alleles, REF or ALT
+    pub n_het: u32,
+    /// Calls with one ALT allele only, whichever ALT it is
+    pub n_homalt: u32,
+    /// Calls with every allele missing, and partial ones with `HalfMissing::Drop`
+    pub n_miss: u32,
+    /// Heterozygous calls written phased (`0|1`)
+    pub n_phased: u32,
+    /// Fully called diploid calls, the only ones HWE, ExcHet and InbreedingCoeff use
+    pub n_diploid: u32,
+    /// Per ALT allele
+    pub af: Vec<f64>,
+    /// Per ALT allele
+    pub maf: Vec<f64>,
+    /// Per ALT allele
+    pub mac: Vec<u32>,
+    /// Per ALT allele: het calls carrying it (1/2 counts for both)
+    pub n_het_alt: Vec<u32>,
+    /// Per ALT allele: calls homozygous for it
+    pub n_homalt_alt: Vec<u32>,
+    /// Per ALT allele: fully called diploid calls heterozygous for it
+    pub n_diploid_het_alt: Vec<u32>,
+    /// Per ALT allele: fully called diploid calls homozygous for it
+    pub n_diploid_homalt_alt: Vec<u32>,
+    /// Per ALT allele: copies of it in heterozygous calls
+    pub ac_het: Vec<u32>,
+    /// Per ALT allele: copies of it in homozygous calls
+    pub ac_hom: Vec<u32>,
+    /// Per ALT allele: 1 - obs/exp het, None if none expected
+    pub inbreeding: Vec<Option<f64>>,
+    /// HWE exact test p-value, None if not biallelic or no diploid calls
+    pub hwe: Option<f64>,
+    /// Excess heterozygosity p-value, 1.0 when nothing to test
+    pub exc_het: Option<f64>,
+    /// Mean allele balance of the heterozygous calls, see [`het_allele_balance`]
+    pub het_ab: Option<f64>,
+}
+
+impl AfStats {
+    /// Samples of the group, called or not
+    pub fn n_samples(&self) -> u32 {
+        self.n_hemi + self.n_partial + self.n_homref + self.n_het + self.n_homalt + self.n_miss
+    }
+
+    /// Fraction of the group's samples with at least one called allele
+    pub fn call_rate(&self) -> f64 {
+        let n = self.n_samples();
+        if n == 0 {
+            0.0
+        } else {
+            (n - self.n_miss) as f64 / n as f64
+        }
+    }
+
+    /// Whether the group has at least `min_an` alleles and `min_call_rate` called samples
+    pub fn passes(&self, min_an: u32, min_call_rate: f64) -> bool {
+        self.an >= min_an && self.call_rate() >= min_call_rate
+    }
+}
+
+/// Weir & Cockerham (1984) Fst between two groups at a biallelic site, from
+/// their fully called diploid genotypes (`n_diploid` and the `n_diploid_*_alt`
+/// counts); haploid, polyploid and partially missing calls are left out. Negative
+/// estimates are kept
+pub fn calc_fst(pops: [&AfStats; 2]) -> Option<f64> {
+    if pops.iter().any(|st| st.ac.len() != 2) {
+        return None;
+    }
+    let r = 2.0;
+    // per group: sample size, ALT frequency, heterozygote frequency
+    let mut n = [0.0; 2];
+    let mut p = [0.0; 2];
+    let mut h = [0.0; 2];
+    for (i, st) in pops.iter().enumerate() {
+        let n_het = st.n_diploid_het_alt[0] as f64;
+        let n_homalt = st.n_diploid_homalt_alt[0] as f64;
+        n[i] = st.n_diploid as f64;
+        if n[i] == 0.0 {
+            return None;
+        }
+        p[i] = (n_het + 2.0 * n_homalt) / (2.0 * n[i]);
+        h[i] = n_het / n[i];
+    }
+    let n_bar = (n[0] + n[1]) / r;
+    if n_bar <= 1.0 {
+        return None;
+    }
+    let n_c = (r * n_bar - (n[0] * n[0] + n[1] * n[1]) / (r * n_bar)) / (r - 1.0);
+    let p_bar = (n[0] * p[0] + n[1] * p[1]) / (r * n_bar);
+    let s2 = (n[0] * (p[0] - p_bar).powi(2) + n[1] * (p[1] - p_bar).powi(2)) / ((r - 1.0) * n_bar);
+    let h_bar = (n[0] * h[0] + n[1] * h[1]) / (r * n_bar);
+    let pq = p_bar * (1.0 - p_bar);
+
+    let a = n_bar / n_c * (s2 - (pq - (r - 1.0) / r * s2 - h_bar / 4.0) / (n_bar - 1.0));
+    let b = n_bar / (n_bar - 1.0)
+        * (pq - (r - 1.0) / r * s2 - (2.0 * n_bar - 1.0) / (4.0 * n_bar) * h_bar);
+    let c = h_bar / 2.0;
+    let total = a + b + c;
+    (total != 0.0).then(|| a / total)
+}
+
+/// 2x2 allele-count test between two groups at a biallelic site: the Pearson
+/// chi-square statistic and its p-value, which comes from Fisher's exact test
+/// when an expected count is below 5
+pub fn calc_assoc(pops: [&AfStats; 2]) -> Option<(f64, f64)> {
+    if pops.iter().any(|st| st.ac.len() != 2) {
+        return None;
+    }
+    let [a, c] = pops.map(|st| st.ac[1] as f64);
+    let [r1, r2] = pops.map(|st| st.an as f64);
+    let (b, d) = (r1 - a, r2 - c);
+    let (c1, c2) = (a + c, b + d);
+    let n = r1 + r2;
+    if r1 == 0.0 || r2 == 0.0 || c1 == 0.0 || c2 == 0.0 {
+        return None;
+    }
+    let chisq = n * (a * d - b * c).powi(2) / (r1 * r2 * c1 * c2);
+    let min_expected = r1.min(r2) * c1.min(c2) / n;
+    let p = if min_expected < 5.0 {
+        fisher_exact(a as u64, r1 as u64, r2 as u64, c1 as u64)
+    } else {
+        // survival function of the chi-square distribution with 1 df
+        erfc((chisq / 2.0).sqrt())
+    };
+    Some((chisq, p))
+}
+
+/// Per ALT, Fisher's exact p-value and odds ratio of (AC, AN - AC) between two groups;
+/// both are missing when a group has no called allele, the odds ratio also when it
+/// would divide by zero
+pub fn calc_compare(pops: [&AfStats; 2]) -> Vec<(Option<f64>, Option<f64>)> {
+    let [r1, r2] = pops.map(|st| st.an as u64);
+    (1..pops[0].ac.len())
+        .map(|i| {
+            if r1 == 0 || r2 == 0 {
+                return (None, None);
+            }
+            let [a, c] = pops.map(|st| st.ac[i] as u64);
+            let (b, d) = (r1 - a, r2 - c);
+            let p = fisher_exact(a, r1, r2, a + c);
+            let or = (b * c != 0).then(|| (a * d) as f64 / (b * c) as f64);
+            (Some(p), or)
+        })
+        .collect()
+}
+
+/// Two-sided Fisher's exact test of a 2x2 table with `x` in the first cell,
+/// row sums `r1`, `r2` and first column sum `c1`
+fn fisher_exact(x: u64, r1: u64, r2: u64, c1: u64) -> f64 {
+    let lo = c1.saturating_sub(r2);
+    let hi = r1.min(c1);
+    let mut probs = vec![0f64; (hi - lo + 1) as usize];
+    let ratio = |k: u64| {
+        // P(k + 1) / P(k) of the hypergeometric distribution
+        ((r1 - k) * (c1 - k)) as f64 / ((k + 1) * (r2 + k + 1 - c1)) as f64
+    };
+
+    // start at the most likely table, so the recursion neither under- nor overflows
+    let mode = ((r1 + 1) * (c1 + 1) / (r1 + r2 + 2)).clamp(lo, hi);
+    probs[(mode - lo) as usize] = 1.0;
+    for k in (lo..mode).rev() {
+        probs[(k - lo) as usize] = probs[(k + 1 - lo) as usize] / ratio(k);
+    }
+    for k in mode..hi {
+        probs[(k + 1 - lo) as usize] = probs[(k - lo) as usize] * ratio(k);
+    }
+    let sum = probs.iter().sum::<f64>();
+    let p_obs = probs[(x - lo) as usize];
+    // relative tolerance so ties with the observed table are not lost to rounding
+    let p = probs
+        .iter()
+        .filter(|&&p| p <= p_obs * (1.0 + 1e-7))
+        .sum::<f64>();
+    (p / sum).min(1.0)
+}
+
+/// Complementary error function (Numerical Recipes `erfcc`, relative error below 1.2e-7)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807
+                            + t * (-1.13520398
+                                + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let r = t * poly.exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+/// Exact test for Hardy-Weinberg equilibrium (Wigginton et al. 2005), as
+/// implemented by bcftools +fill-tags. Returns `(HWE, ExcHet)` p-values, or
+/// `None` when there are no diploid genotypes to test.
+pub fn calc_hwe(n_homref: u32, n_het: u32, n_homalt: u32) -> Option<(f64, f64)> {
+    let ngt = (n_homref + n_het + n_homalt) as usize;
+    if ngt == 0 {
+        return None;
+    }
+    let nref = 2 * n_homref as usize + n_het as usize;
+    let nalt = 2 * n_homalt as usize + n_het as usize;
+    let nhet = n_het as usize;
+    let nrare = nref.min(nalt);
+
+    let mut probs = vec![0f64; nrare + 1];
+
+    // start at the most likely number of hets, with the same parity as nrare
+    let mut mid = nrare * (nref + nalt - nrare) / (nref + nalt);
+    if (nrare & 1) ^ (mid & 1) == 1 {
+        mid += 1;
+    }
+    probs[mid] = 1.0;
+    let mut sum = 1.0;
+
+    let mut hom_r = (nrare - mid) / 2;
+    let mut hom_c = ngt - mid - hom_r;
+    let mut het = mid;
+    while het > 1 {
+        probs[het - 2] = probs[het] * het as f64 * (het as f64 - 1.0)
+            / (4.0 * (hom_r as f64 + 1.0) * (hom_c as f64 + 1.0));
+        sum += probs[het - 2];
+        hom_r += 1;
+        hom_c += 1;
+        het -= 2;
+    }
+
+    let mut hom_r = (nrare - mid) / 2;
+    let mut hom_c = ngt - mid - hom_r;
+    let mut het = mid;
+    while het + 2 <= nrare {
+        probs[het + 2] = probs[het] * 4.0 * hom_r as f64 * hom_c as f64
+            / ((het as f64 + 2.0) * (het as f64 + 1.0));
+        sum += probs[het + 2];
+        hom_r -= 1;
+        hom_c -= 1;
+        het += 2;
+    }
+
+    for p in probs.iter_mut() {
+        *p /= sum;
+    }
+
+    let exc_het = probs[nhet..].iter().sum::<f64>();
+    let hwe = probs
+        .iter()
+        .filter(|&&p| p <= probs[nhet])
+        .sum::<f64>()
+        .min(1.0);
+    Some((hwe, exc_het))
+}
+
+/// All statistics of one group from the genotypes of its samples at a site with
+/// `n_allele` alleles (REF included), like `calc_af` of truvari's grpaf.py
+///
+/// ```
+/// use vcfgrpaf::{calc_af, Gt, HalfMissing};
+///
+/// let gts: Vec<Gt> = vec![vec![Some(0), Some(1)], vec![Some(1), Some(1)], vec![None, None]];
+/// let st = calc_af(&gts.iter().collect::<Vec<_>>(), 2, HalfMissing::Partial);
+/// assert_eq!((st.ac, st.an), (vec![1, 3], 4));
+/// assert_eq!((st.n_het, st.n_homalt, st.n_miss), (1, 1, 1));
+/// ```
+pub fn calc_af(genotypes: &[&Gt], n_allele: usize, half_missing: HalfMissing) -> AfStats {
+    calc_af_with(genotypes, n_allele, half_missing, true)
+}
+
+/// `calc_af`, leaving HWE/ExcHet at `None` unless `exact_test`, as the test is the
+/// costly part
+fn calc_af_with(
+    genotypes: &[&Gt],
+    n_allele: usize,
+    half_missing: HalfMissing,
+    exact_test: bool,
+) -> AfStats {
+    let n_alt = n_allele.saturating_sub(1);
+    let mut st = AfStats {
+        ac: vec![0; n_allele],
+        n_het_alt: vec![0; n_alt],
+        n_homalt_alt: vec![0; n_alt],
+        ac_het: vec![0; n_alt],
+        ac_hom: vec![0; n_alt],
+        ..Default::default()
+    };
+
+    // fully called diploid genotypes, for the inbreeding coefficient and the exact test
+    let mut n_dip = 0u32;
+    let mut dip_ac = vec![0u32; n_allele];
+    let mut dip_het = vec![0u32; n_alt];
+    let mut dip_homalt = vec![0u32; n_alt];
+
+    for alleles in genotypes {
+        // indices come from the file: one past the site's alleles counts as missing
+        let checked: Gt;
+        let alleles = if alleles.iter().flatten().any(|&a| a as usize >= n_allele) {
+            checked = alleles
+                .iter()
+                .map(|a| a.filter(|&a| (a as usize) < n_allele))
+                .collect();
+            &checked
+        } else {
+            *alleles
+        };
+        if let [Some(a), Some(b)] = alleles[..] {
+            n_dip += 1;
+            dip_ac[a as usize] += 1;
+            dip_ac[b as usize] += 1;
+            if a != b {
+                for x in [a, b].into_iter().filter(|&x| x > 0) {
+                    dip_het[x as usize - 1] += 1;
+                }
+            } else if a > 0 {
+                dip_homalt[a as usize - 1] += 1;
+            }
+        }
+        let called: Vec<u32> = alleles.iter().flatten().copied().collect();
+        let partial = !called.is_empty() && called.len() < alleles.len();
+        if called.is_empty() || (partial && half_missing == HalfMissing::Drop) {
+            st.n_miss += 1;
+            continue;
+        }
+        for &x in &called {
+            st.an += 1;
+            st.ac[x as usize] += 1;
+        }
+
+        if partial && half_missing == HalfMissing::Partial {
+            st.n_partial += 1;
+        } else if alleles.len() == 1 || (partial && half_missing == HalfMissing::Hemi) {
+            // haploid, or some alleles missing
+            st.n_hemi += 1;
+        } else {
+            let first = called[0];
+            if called.iter().all(|&a| a == first) {
+                if first == 0 {
+                    st.n_homref += 1;
+                } else {
+                    st.n_homalt += 1;
+                    st.n_homalt_alt[first as usize - 1] += 1;
+                    st.ac_hom[first as usize - 1] += called.len() as u32;
+                }
+            } else {
+                st.n_het += 1;
+                // every distinct ALT of the call, e.g. both 1 and 2 for 0/1/1/2
+                for (i, &x) in called.iter().enumerate() {
+                    if x > 0 {
+                        st.ac_het[x as usize - 1] += 1;
+                        if !called[..i].contains(&x) {
+                            st.n_het_alt[x as usize - 1] += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    st.n_diploid = n_dip;
+    st.af = vec![0.0; n_alt];
+    st.maf = vec![0.0; n_alt];
+    st.mac = vec![0; n_alt];
+    if st.an > 0 {
+        for i in 0..n_alt {
+            let ac = st.ac[i + 1];
+            st.af[i] = ac as f64 / st.an as f64;
+            st.mac[i] = ac.min(st.an - ac);
+            st.maf[i] = st.mac[i] as f64 / st.an as f64;
+        }
+    }
+    st.inbreeding = (0..n_alt)
+        .map(|i| {
+            let p = dip_ac[i + 1] as f64 / (2 * n_dip).max(1) as f64;
+            let expected = 2.0 * p * (1.0 - p) * n_dip as f64;
+            (expected > 0.0).then(|| 1.0 - dip_het[i] as f64 / expected)
+        })
+        .collect();
+    // the exact test is only defined for biallelic diploid calls, so haploid and
+    // polyploid calls (which still count toward AC/AN) are left out of it
+    if n_allele == 2 && exact_test {
+        let dip_homref = n_dip - dip_het[0] - dip_homalt[0];
+        match calc_hwe(dip_homref, dip_het[0], dip_homalt[0]) {
+            Some((hwe, exc_het)) => {
+                st.hwe = Some(hwe);
+                st.exc_het = Some(exc_het);
+            }
+            // nothing to test: no evidence of excess heterozygosity
+            None => st.exc_het = Some(1.0),
+        }
+    }
+    st.n_diploid_het_alt = dip_het;
+    st.n_diploid_homalt_alt = dip_homalt;
+    st
+}
+
+/// Statistics of the default set, in output order
+pub const WANT_TAGS: [&str; 19] = [
+    "AF",
+    "MAF",
+    "MAC",
+    "AC",
+    "AN",
+    "N_HEMI",
+    "N_MISS",
+    "N_CALLED",
+    "N_HOMREF",
+    "N_HET",
+    "N_HOMALT",
+    "HWE",
+    "ExcHet",
+    "F_MISSING",
+    "AC_Het",
+    "AC_Hom",
+    "SINGLETON",
+    "DOUBLETON",
+    "N_PARTIAL",
+];
+
+/// Statistics only computed when asked for
+pub const OPTIONAL_TAGS: [&str; 7] = [
+    "InbreedingCoeff",
+    "N_PHASED",
+    "RARE",
+    "HET_AB",
+    "N_SINGLETON",
+    "N_DOUBLETON",
+    "N_DIPLOID",
+];
+
+/// The static name of a statistic given by name
+pub fn known_tag(tag: &str) -> Option<&'static str> {
+    WANT_TAGS
+        .iter()
+        .chain(&OPTIONAL_TAGS)
+        .find(|t| **t == tag)
+        .copied()
+}
+
+/// Which statistics to compute, in output order; [`WANT_TAGS`] by default
+///
+/// ```
+/// use vcfgrpaf::StatsConfig;
+///
+/// let config = StatsConfig::only(&["AF", "AN"])?.with("HWE")?;
+/// assert_eq!(config.tags(), ["AF", "AN", "HWE"]);
+/// # anyhow::Ok(())
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsConfig {
+    tags: Vec<&'static str>,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        StatsConfig {
+            tags: WANT_TAGS.to_vec(),
+        }
+    }
+}
+
+impl StatsConfig {
+    /// The default set, [`WANT_TAGS`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only these statistics, in this order
+    pub fn only<S: AsRef<str>>(tags: &[S]) -> anyhow::Result<Self> {
+        let mut config = StatsConfig { tags: Vec::new() };
+        let unknown: Vec<&str> = tags
+            .iter()
+            .map(|t| t.as_ref())
+            .filter(|t| known_tag(t).is_none())
+            .collect();
+        if !unknown.is_empty() {
+            anyhow::bail!(
+                "unknown statistic(s) {}; known ones are {}",
+                unknown.join(", "),
+                WANT_TAGS
+                    .iter()
+                    .chain(&OPTIONAL_TAGS)
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join(",")
+            );
+        }
+        for tag in tags {
+            config = config.with(tag.as_ref())?;
+        }
+        Ok(config)
+    }
+
+    /// Also compute `tag`, after the others
+    pub fn with(mut self, tag: &str) -> anyhow::Result<Self> {
+        let tag = known_tag(tag).with_context(|| format!("unknown statistic {tag}"))?;
+        if !self.tags.contains(&tag) {
+            self.tags.push(tag);
+        }
+        Ok(self)
+    }
+
+    /// Leave `tag` out
+    pub fn without(mut self, tag: &str) -> Self {
+        self.tags.retain(|t| *t != tag);
+        self
+    }
+
+    /// Statistics to compute, in output order
+    pub fn tags(&self) -> &[&'static str] {
+        &self.tags
+    }
+
+    /// Whether `tag` is one of them
+    pub fn wants(&self, tag: &str) -> bool {
+        self.tags.contains(&tag)
+    }
+}
+
+/// Statistics of sample groups from the decoded genotypes of one site
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Annotator {
+    /// How partially missing calls are counted
+    pub half_missing: HalfMissing,
+    /// Statistics to compute; the others may be left at their defaults
+    pub config: StatsConfig,
+}
+
+impl Annotator {
+    /// Computes the default statistics, counting partially missing calls as `half_missing`
+    pub fn new(half_missing: HalfMissing) -> Self {
+        Annotator {
+            half_missing,
+            config: StatsConfig::default(),
+        }
+    }
+
+    /// Only compute the statistics of `config`
+    pub fn config(mut self, config: StatsConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Statistics of the samples selected by `mask`; `phased` (one per sample) fills
+    /// in `n_phased`
+    pub fn group_stats(
+        &self,
+        gts: &[Gt],
+        phased: Option<&[bool]>,
+        n_allele: usize,
+        mask: &[bool],
+    ) -> AfStats {
+        let exact_test = self.config.wants("HWE") || self.config.wants("ExcHet");
+        let mut st = calc_af_with(
+            &collect_gts(gts, mask),
+            n_allele,
+            self.half_missing,
+            exact_test,
+        );
+        if let Some(phased) = phased.filter(|_| self.config.wants("N_PHASED")) {
+            st.n_phased = count_phased_het(gts, phased, mask);
+        }
+        st
+    }
+
+    /// Statistics of every group of `masks`, by group name
+    pub fn stats<'a>(
+        &self,
+        gts: &[Gt],
+        phased: Option<&[bool]>,
+        n_allele: usize,
+        masks: &'a HashMap<String, Vec<bool>>,
+    ) -> HashMap<&'a str, AfStats> {
+        masks
+            .iter()
+            .map(|(grp, mask)| (grp.as_str(), self.group_stats(gts, phased, n_allele, mask)))
+            .collect()
+    }
+}
+
+/// Heterozygous calls among the masked samples that are phased
+pub fn count_phased_het(all_gts: &[Gt], phased: &[bool], mask: &[bool]) -> u32 {
+    all_gts
+        .iter()
+        .zip(phased)
+        .zip(mask)
+        .filter(|((gt, &ph), &m)| {
+            m && ph
+                && gt.len() > 1
+                && gt.iter().all(|a| a.is_some())
+                && gt.iter().any(|a| *a != gt[0])
+        })
+        .count() as u32
+}
+
+/// Mean allele balance of the masked heterozygous diploid calls: reads of the higher allele
+/// over reads of both, from each sample's AD. Calls without AD for both alleles are left
+/// out; None when no call is left
+pub fn het_allele_balance(all_gts: &[Gt], ad: &[Vec<Option<u32>>], mask: &[bool]) -> Option<f64> {
+    let mut sum = 0.0;
+    let mut n = 0;
+    for ((gt, ad), _) in all_gts.iter().zip(ad).zip(mask).filter(|(_, &m)| m) {
+        let [Some(a), Some(b)] = gt[..] else {
+            continue;
+        };
+        if a == b {
+            continue;
+        }
+        let (lo, hi) = (a.min(b) as usize, a.max(b) as usize);
+        let (Some(Some(lo)), Some(Some(hi))) = (ad.get(lo), ad.get(hi)) else {
+            continue;
+        };
+        if lo + hi > 0 {
+            sum += *hi as f64 / (lo + hi) as f64;
+            n += 1;
+        }
+    }
+    (n > 0).then(|| sum / n as f64)
+}
+
+/// Genotypes of the samples selected by `mask`
+pub fn collect_gts<'a>(all_gts: &'a [Gt], mask: &[bool]) -> Vec<&'a Gt> {
+    all_gts
+        .iter()
+        .zip(mask)
+        .filter(|(_, &m)| m)
+        .map(|(gt, _)| gt)
+        .collect()
+}
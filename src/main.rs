@@ -2,13 +2,18 @@
 use anyhow::{Context, Result};
 use clap::Parser;
 use csv::ReaderBuilder;
-use rust_htslib::{
-    bcf::{self, Header, HeaderRecord, Read, Reader as BcfReader, Writer},
+use log::{debug, info, warn};
+use rayon::prelude::*;
+use rust_htslib::bcf::{
+    self, header::TagType, record::Buffer, record::Numeric, Header, HeaderRecord, IndexedReader,
+    Read, Reader as BcfReader, Writer,
 };
 
-use std::{
-
-    collections::{HashMap, HashSet}
+use std::collections::{HashMap, HashSet};
+use std::io::{Read as _, Write};
+use vcfgrpaf::{
+    calc_assoc, calc_compare, calc_fst, het_allele_balance, AfStats, Annotator, Gt, HalfMissing,
+    StatsConfig, OPTIONAL_TAGS, WANT_TAGS,
 };
 
 #[derive(Parser, Debug)]
@@ -18,130 +23,2399 @@ use std::{
     about = "This program is the rust re-implimentation of grpaf.py from truvari"
 )]
 struct Opts {
-    /// VCF input
+    /// VCF input, `-` for stdin, or an http(s)://, ftp://, s3:// or gs:// URL
     #[arg(value_name = "VCF")]
     input: String,
 
     /// Output VCF
-    #[arg(short, long)]
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["check", "dry_run"],
+        default_value = ""
+    )]
     output: String,
 
-    /// Tab‑delimited 2‑col file: <sample> <group>
+    /// Tab‑delimited 2‑col file: <sample> <group>[,<group>...], optionally gzipped (.gz)
     #[arg(short, long)]
     labels: String,
+
+    /// Column separator of --labels; auto looks for a tab, then a comma, then blanks
+    #[arg(long, value_enum, default_value_t = LabelsDelim::Auto)]
+    labels_delim: LabelsDelim,
+
+    /// The first line of --labels holds column names
+    #[arg(long)]
+    labels_has_header: bool,
+
+    /// Layout of --labels: a sample/group table, or a PLINK .fam or .psam sample file
+    #[arg(long, value_enum, default_value_t = LabelsFormat::Tsv)]
+    labels_format: LabelsFormat,
+
+    /// Column of --labels holding the sample names: 1-based index or header name
+    /// [default: 1, IID for fam and psam]
+    #[arg(long, value_name = "COL", value_parser = LabelsCol::parse)]
+    sample_col: Option<LabelsCol>,
+
+    /// Column of --labels holding the group(s): 1-based index or header name
+    /// [default: 2, FID for fam and psam]
+    #[arg(long, value_name = "COL", value_parser = LabelsCol::parse)]
+    group_col: Option<LabelsCol>,
+
+    /// Also emit unsuffixed AF/AC/AN/... computed over all samples; they replace the
+    /// input's own AF/AC/AN with a warning (--skip-existing keeps them)
+    #[arg(long)]
+    include_total: bool,
+
+    /// Also emit AF/AC/AN/... over all samples, suffixed with --total-label
+    #[arg(long)]
+    with_total: bool,
+
+    /// Group suffix used by --with-total
+    #[arg(long, default_value = "ALL")]
+    total_label: String,
+
+    /// Also annotate a group of this name holding every sample not in a group of --labels
+    #[arg(long, value_name = "NAME")]
+    complement_group: Option<String>,
+
+    /// Tab/space-delimited <group> <new name> file to rename groups of --labels before
+    /// they are used in the INFO ids
+    #[arg(long, value_name = "FILE")]
+    rename_groups: Option<String>,
+
+    /// b: compressed BCF, u: uncompressed BCF, z: compressed VCF, v: uncompressed VCF
+    /// [default: guessed from the --output extension]
+    #[arg(short = 'O', long, value_enum)]
+    output_type: Option<OutputType>,
+
+    /// Index the output once it is written: .csi for BCF, .tbi for bgzipped VCF
+    #[arg(long)]
+    write_index: bool,
+
+    /// Only process these regions: chr, chr:pos, chr:start- or chr:start-end (1-based),
+    /// comma-separated or repeated. Needs an indexed VCF
+    #[arg(short, long, value_name = "REGION", value_delimiter = ',')]
+    regions: Vec<String>,
+
+    /// Only process the regions in this BED file. Needs an indexed VCF
+    #[arg(long, value_name = "BED")]
+    regions_file: Option<String>,
+
+    /// Only annotate records overlapping these regions, same syntax as --regions. Streams the
+    /// whole file, so no index is needed
+    #[arg(short, long, value_name = "REGION", value_delimiter = ',')]
+    targets: Vec<String>,
+
+    /// Only annotate records overlapping the intervals of this BED file. No index is needed
+    #[arg(long, value_name = "BED")]
+    targets_file: Option<String>,
+
+    /// Only annotate records on these contigs, comma-separated. Streams the whole file, so no
+    /// index is needed
+    #[arg(long, value_name = "CONTIG", value_delimiter = ',')]
+    chroms: Vec<String>,
+
+    /// Drop records outside --targets/--targets-file/--chroms instead of writing them unchanged
+    #[arg(long)]
+    drop_untargeted: bool,
+
+    /// Only count these samples (comma-separated); the others are left out of every group
+    #[arg(short, long, value_name = "SAMPLE", value_delimiter = ',')]
+    samples: Vec<String>,
+
+    /// Only count the samples listed in this file, one per line
+    #[arg(long, value_name = "FILE")]
+    samples_file: Option<String>,
+
+    /// 6-column PED file (FID IID father mother sex phenotype) of the cohort's families
+    #[arg(long, value_name = "FILE", requires = "founders_only")]
+    pedigree: Option<String>,
+
+    /// Only count the founders of --pedigree, samples whose parents are not in it, so
+    /// relatives don't inflate the frequencies; samples missing from it are counted
+    #[arg(long, requires = "pedigree")]
+    founders_only: bool,
+
+    /// Write a VCF without genotypes unchanged, apart from the new header lines, instead of failing
+    #[arg(long)]
+    allow_sites_only: bool,
+
+    /// Deprecated: failing on INFO ids the input already defines is the default
+    #[arg(long, hide = true)]
+    strict: bool,
+
+    /// Overwrite the values of INFO ids to be added that the input already defines,
+    /// keeping their header definitions, and an existing --output file
+    #[arg(long, conflicts_with = "skip_existing")]
+    force: bool,
+
+    /// Leave INFO ids to be added that the input already defines as they are, writing
+    /// only the new ones, e.g. to add groups to a file annotated before
+    #[arg(long, alias = "keep-old-tags", conflicts_with = "overwrite_existing")]
+    skip_existing: bool,
+
+    /// Only check the labels against the VCF header and the new INFO ids against the existing
+    /// ones, without reading records or writing output
+    #[arg(long)]
+    check: bool,
+
+    /// Print the groups with their sample counts and the INFO header lines that would be
+    /// added, then exit without reading records or writing output
+    #[arg(long, conflicts_with = "check")]
+    dry_run: bool,
+
+    /// Only warn (instead of failing) when --labels names samples missing from the VCF
+    #[arg(long)]
+    lenient: bool,
+
+    /// Fail when a VCF sample is not assigned to any group in --labels
+    #[arg(long)]
+    strict_labels: bool,
+
+    /// Remove all AF_*/AC_*/HWE_*/... INFO fields already in the input before annotating
+    #[arg(long)]
+    overwrite_existing: bool,
+
+    /// A group fails a site when its AN there is below this
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    min_an: u32,
+
+    /// A group fails a site when less than this fraction of its samples have a called genotype
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.0)]
+    min_call_rate: f64,
+
+    /// What to do when a group fails --min-an/--min-call-rate: leave its AF/MAF missing, or
+    /// drop the whole record
+    #[arg(long, value_enum, default_value_t = FailAction::Missing)]
+    fail_action: FailAction,
+
+    /// What to do with a group that has no samples in the VCF (after --samples and
+    /// --founders-only): fail, leave it out of the output, or write its statistics as missing
+    #[arg(long, value_enum, default_value_t = EmptyGroupAction::Missing)]
+    empty_group_action: EmptyGroupAction,
+
+    /// What to do with a record whose genotypes can't be decoded; a record that can't be
+    /// read always stops the run
+    #[arg(long, value_enum, default_value_t = OnError::Fail)]
+    on_error: OnError,
+
+    /// Leave records whose ALT allele count over all samples is below N unannotated
+    #[arg(long, value_name = "N")]
+    min_ac: Option<u32>,
+
+    /// Drop the records below --min-ac instead of writing them unannotated
+    #[arg(long, requires = "min_ac")]
+    drop_below_min_ac: bool,
+
+    /// Also write the per-site statistics of every group to this file as newline-delimited JSON
+    #[arg(long, value_name = "PATH")]
+    stats_json: Option<String>,
+
+    /// Also write one line per group of genome-wide totals over the annotated records to
+    /// this TSV file at the end of the run
+    #[arg(long, value_name = "PATH")]
+    summary_out: Option<String>,
+
+    /// Only write these statistics for each group, e.g. AF,AC,AN [default: all but the
+    /// optional InbreedingCoeff, N_PHASED, RARE, HET_AB, N_SINGLETON, N_DOUBLETON and
+    /// N_DIPLOID]
+    #[arg(long = "tags", value_name = "TAG", value_delimiter = ',')]
+    select_tags: Vec<String>,
+
+    /// Also write InbreedingCoeff_<grp>, 1 - observed/expected heterozygosity per ALT
+    #[arg(long)]
+    with_inbreeding: bool,
+
+    /// Count `*` (spanning deletion) alleles as missing, so they are left out of AN
+    #[arg(long)]
+    exclude_star: bool,
+
+    /// Count a genotype as missing unless its per-sample FORMAT filter has this
+    /// value, e.g. FT=PASS
+    #[arg(long, value_name = "TAG=VALUE")]
+    sample_filter: Option<String>,
+
+    /// Count a genotype as missing when its FORMAT/GQ is below N
+    #[arg(long, value_name = "N")]
+    min_gq: Option<i32>,
+
+    /// Count a genotype as missing when its FORMAT/DP is below N
+    #[arg(long, value_name = "N")]
+    min_dp: Option<i32>,
+
+    /// How to count a partially missing call such as `./1`
+    #[arg(long, alias = "half-call-mode", value_enum, default_value_t = HalfMissingPolicy::Partial)]
+    half_missing_policy: HalfMissingPolicy,
+
+    /// Also write N_PHASED_<grp>, the number of phased heterozygous calls
+    #[arg(long)]
+    with_phased: bool,
+
+    /// Also write HET_AB_<grp>, the mean allele balance of the heterozygous calls, from
+    /// FORMAT/AD
+    #[arg(long)]
+    with_het_ab: bool,
+
+    /// Also set the RARE_<grp> flag where an ALT has 1 to N copies in the group
+    #[arg(long, value_name = "N")]
+    rare_threshold: Option<u32>,
+
+    /// Also write N_ALT, the number of ALT alleles of each record
+    #[arg(long)]
+    annotate_nalt: bool,
+
+    /// Write FST_<A>_<B>, Weir & Cockerham's Fst between groups A and B, at
+    /// biallelic sites (can be repeated)
+    #[arg(long, value_name = "A,B")]
+    fst: Vec<String>,
+
+    /// Write CHISQ_<A>_<B> and P_<A>_<B>, an allelic association test between groups A
+    /// and B, at biallelic sites (can be repeated)
+    #[arg(long, value_name = "A,B")]
+    assoc: Vec<String>,
+
+    /// Write FISHER_<A>_<B> and OR_<A>_<B> per ALT, Fisher's exact test and odds ratio
+    /// of the allele counts of groups A and B (can be repeated)
+    #[arg(long, value_name = "A:B")]
+    compare: Vec<String>,
+
+    /// Write the statistics of each group as one GRPAF_<grp> String field, e.g.
+    /// `AF:0.5|AC:3|AN:6`, instead of one INFO field per statistic
+    #[arg(long)]
+    compact_string: bool,
+
+    /// Number of AC_<grp>: A for the ALT counts, R to lead with the REF count as gnomAD v4
+    #[arg(long, value_enum, ignore_case = true, default_value_t = AcNumber::A)]
+    ac_number: AcNumber,
+
+    /// Names of the het/hom-alt count tags
+    #[arg(long, value_enum, default_value_t = Naming::Truvari)]
+    naming: Naming,
+
+    /// INFO id of a group's statistic, from the `{stat}` and `{group}` placeholders,
+    /// e.g. `{group}_{stat}`
+    #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_NAME_TEMPLATE)]
+    name_template: String,
+
+    /// Only process records whose FILTER is one of these, comma-separated; `.` is an unset
+    /// FILTER, e.g. PASS,.
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    apply_filters: Vec<String>,
+
+    /// Skip records matching this test, e.g. 'QUAL<30' or 'INFO/DP<100'; may be repeated
+    #[arg(long, value_name = "EXPR", value_parser = Exclude::parse)]
+    exclude: Vec<Exclude>,
+
+    /// What happens to records rejected by --apply-filters or --exclude
+    #[arg(long, value_enum, default_value_t = FilterMode::Drop)]
+    filter_mode: FilterMode,
+
+    /// Tab/space-delimited <sample> <sex> (M/F) file; males are counted as haploid on chrX
+    /// and chrY outside the pseudo-autosomal regions, and females are left out on chrY
+    #[arg(long, value_name = "FILE")]
+    sex_file: Option<String>,
+
+    /// BED file of the pseudo-autosomal regions of chrX/chrY, where males stay diploid
+    #[arg(long, value_name = "BED", requires = "sex_file")]
+    par_regions: Option<String>,
+
+    /// Name of the X chromosome [default: X or chrX]
+    #[arg(long, value_name = "CONTIG", requires = "sex_file")]
+    x_contig: Option<String>,
+
+    /// Name of the Y chromosome [default: Y or chrY]
+    #[arg(long, value_name = "CONTIG", requires = "sex_file")]
+    y_contig: Option<String>,
+
+    /// Write an annotated VCF, a table with one row per ALT allele, or one JSON object per
+    /// variant
+    #[arg(long, value_enum, default_value_t = OutputFormat::Vcf)]
+    format: OutputFormat,
+
+    /// Write a sites-only VCF, without FORMAT and sample columns
+    #[arg(long, alias = "sites-only")]
+    drop_genotypes: bool,
+
+    /// Remove every INFO field of the input, keeping only the ones computed here
+    #[arg(long)]
+    strip_info: bool,
+
+    /// Threads htslib uses to (de)compress the input and output, 1 for none beyond the main one
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    io_threads: usize,
+
+    /// Report progress on stderr every N variants, 0 to disable
+    #[arg(long, value_name = "N", default_value_t = 10000)]
+    progress_every: u64,
+
+    /// No progress lines or summary on stderr; warnings are still printed
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// More detail on stderr, -vv for the most; RUST_LOG overrides both this and --quiet
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Threads used to compute the statistics, 0 for one per CPU core
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    threads: usize,
 }
 
-/// Per‑variant statistics ----------------------------------------------------
-#[derive(Default, Debug, Clone)]
-struct AfStats {
-    ac: [u32; 2], // allele counts (REF, ALT)
-    an: u32,      // allele number
-    n_hemi: u32,
-    n_homref: u32,
-    n_het: u32,
-    n_homalt: u32,
-    n_miss: u32,
-    af: f64,
-    maf: f64,
-    mac: u32,
-}
-
-/// 计算所有统计量（单群体，等价于 Python 的 calc_af）
-fn calc_af(genotypes: &[Option<[Option<u8>; 2]>]) -> AfStats {
-    let mut st = AfStats::default();
-
-    for g in genotypes {
-        match g {
-            None => st.n_miss += 1,
-            Some(alleles) => {
-                // haploid?
-                if alleles[1].is_none() {
-                    match alleles[0] {
-                        None => st.n_miss += 1,
-                        Some(a) => {
-                            st.an += 1;
-                            st.ac[a as usize] += 1;
-                            st.n_hemi += 1;
-                        }
+/// VCF Number of the AC tags
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum AcNumber {
+    /// One count per ALT allele
+    A,
+    /// The REF count, then one per ALT allele
+    R,
+}
+
+/// INFO naming convention of the genotype-class counts
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Naming {
+    /// N_HET_<grp>, N_HOMALT_<grp> as written by truvari grpaf
+    Truvari,
+    /// nhet_<grp>, nhomalt_<grp> as in gnomAD
+    Gnomad,
+}
+
+impl Naming {
+    fn tag_name(self, tag: &str) -> &str {
+        match (self, tag) {
+            (Naming::Gnomad, "N_HET") => "nhet",
+            (Naming::Gnomad, "N_HOMALT") => "nhomalt",
+            _ => tag,
+        }
+    }
+}
+
+impl Opts {
+    /// The FORMAT tag and the value it must have of --sample-filter
+    fn sample_filter(&self) -> Option<(&str, &str)> {
+        self.sample_filter
+            .as_deref()
+            .and_then(|f| f.split_once('='))
+    }
+
+    /// Statistics to compute from --tags and the --with-* options
+    fn stats_config(&self) -> Result<StatsConfig> {
+        let mut config = if self.select_tags.is_empty() {
+            StatsConfig::new()
+        } else {
+            StatsConfig::only(&self.select_tags).context("--tags")?
+        };
+        for (wanted, tag) in [
+            (self.with_inbreeding, "InbreedingCoeff"),
+            (self.with_phased, "N_PHASED"),
+            (self.with_het_ab, "HET_AB"),
+            (self.rare_threshold.is_some(), "RARE"),
+        ] {
+            if wanted {
+                config = config.with(tag)?;
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// --half-missing-policy values, one per `HalfMissing`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum HalfMissingPolicy {
+    /// Count the whole call as missing: nothing goes to AN, the sample to N_MISS
+    #[value(alias = "missing")]
+    Drop,
+    /// Count the called alleles in AC/AN and the sample in N_PARTIAL
+    Partial,
+    /// Count the called alleles in AC/AN and the sample in N_HEMI, like haploid calls
+    Hemi,
+    /// Count the called alleles in AC/AN and class the sample by them, e.g. `./1` as HOMALT
+    Keep,
+}
+
+impl From<HalfMissingPolicy> for HalfMissing {
+    fn from(policy: HalfMissingPolicy) -> Self {
+        match policy {
+            HalfMissingPolicy::Drop => HalfMissing::Drop,
+            HalfMissingPolicy::Partial => HalfMissing::Partial,
+            HalfMissingPolicy::Hemi => HalfMissing::Hemi,
+            HalfMissingPolicy::Keep => HalfMissing::Keep,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum OnError {
+    /// Stop at the first bad record
+    Fail,
+    /// Warn, write the record unannotated, and go on
+    Skip,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum FilterMode {
+    /// Leave the records out of the output
+    Drop,
+    /// Write the records unchanged, without statistics
+    Passthrough,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum FailAction {
+    Missing,
+    Drop,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum EmptyGroupAction {
+    Error,
+    Skip,
+    Missing,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    Vcf,
+    Tsv,
+    Jsonl,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum OutputType {
+    B,
+    U,
+    Z,
+    V,
+}
+
+impl OutputType {
+    /// Guess the output type from the file name, like bcftools does
+    fn from_path(path: &str) -> Self {
+        if path.ends_with(".bcf") {
+            OutputType::B
+        } else if path.ends_with(".gz") || path.ends_with(".bgz") {
+            OutputType::Z
+        } else {
+            OutputType::V
+        }
+    }
+
+    fn is_bcf(self) -> bool {
+        matches!(self, OutputType::B | OutputType::U)
+    }
+
+    /// `(uncompressed, format)` as taken by `bcf::Writer`
+    fn writer_args(self) -> (bool, bcf::Format) {
+        match self {
+            OutputType::B => (false, bcf::Format::Bcf),
+            OutputType::U => (true, bcf::Format::Bcf),
+            OutputType::Z => (false, bcf::Format::Vcf),
+            OutputType::V => (true, bcf::Format::Vcf),
+        }
+    }
+}
+
+/// Column separator of the labels file
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum LabelsDelim {
+    Auto,
+    Tab,
+    Comma,
+    /// Any run of spaces or tabs
+    Space,
+}
+
+impl LabelsDelim {
+    /// Look for a tab, then a comma, then a run of blanks in the first non-empty line
+    fn sniff(text: &str) -> Self {
+        let first = text.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+        // `S1 EUR,CASE` is a blank-separated line with a group list, not CSV
+        let blanks = first.trim().contains(char::is_whitespace);
+        if first.contains('\t') {
+            LabelsDelim::Tab
+        } else if first.contains(',') && !blanks {
+            LabelsDelim::Comma
+        } else if blanks {
+            LabelsDelim::Space
+        } else {
+            LabelsDelim::Tab
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum LabelsFormat {
+    /// <sample> <group> columns, see --labels-delim and --sample-col/--group-col
+    Tsv,
+    /// PLINK .fam: blank-separated, no header, FID as the group and IID as the sample
+    Fam,
+    /// PLINK 2 .psam: a `#FID`/`#IID` header line, IID as the sample and FID, or
+    /// --group-col, as the group
+    Psam,
+}
+
+/// Column of the labels file, given by `--sample-col`/`--group-col`
+#[derive(Debug, Clone)]
+enum LabelsCol {
+    Index(usize), // 0-based
+    Name(String),
+}
+
+impl LabelsCol {
+    fn parse(s: &str) -> std::result::Result<Self, String> {
+        match s.parse::<usize>() {
+            Ok(0) => Err("column indices start at 1".to_string()),
+            Ok(i) => Ok(LabelsCol::Index(i - 1)),
+            Err(_) => Ok(LabelsCol::Name(s.to_string())),
+        }
+    }
+
+    /// 0-based index of the column, looking names up in the header line
+    fn resolve(&self, header: Option<&csv::StringRecord>, flag: &str) -> Result<usize> {
+        match self {
+            LabelsCol::Index(i) => Ok(*i),
+            LabelsCol::Name(name) => header
+                .and_then(|h| h.iter().position(|col| col.trim_start_matches('#') == name))
+                .with_context(|| format!("{flag} {name}: no such column in the labels header")),
+        }
+    }
+}
+
+/// Samples of a PED file, and whether each one is a founder: neither parent is in the file,
+/// which also covers the missing parent `0`
+fn read_pedigree(path: &str) -> Result<HashMap<String, bool>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading {path}"))?;
+    let mut parents: Vec<(String, [String; 2])> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            anyhow::bail!("{path}:{}: expected FID IID father mother ...", i + 1);
+        }
+        parents.push((
+            fields[1].to_string(),
+            [fields[2].to_string(), fields[3].to_string()],
+        ));
+    }
+    let ids: HashSet<&String> = parents.iter().map(|(id, _)| id).collect();
+    Ok(parents
+        .iter()
+        .map(|(id, [father, mother])| (id.clone(), !ids.contains(father) && !ids.contains(mother)))
+        .collect())
+}
+
+/// `<group> <new name>` lines of --rename-groups
+fn read_renames(path: &str) -> Result<HashMap<String, String>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading {path}"))?;
+    let mut renames = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (Some(from), Some(to)) = (fields.next(), fields.next()) else {
+            anyhow::bail!("{path}:{}: expected <group> <new name>", i + 1);
+        };
+        renames.insert(from.to_string(), to.to_string());
+    }
+    Ok(renames)
+}
+
+/// A group name usable after `<tag>_` in an INFO id: anything but letters, digits, `_`
+/// and `.` becomes `_`
+fn sanitize_group(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Whether the first labels line is a header like `sample<TAB>population` rather than data
+fn looks_like_header(
+    row: &csv::StringRecord,
+    sample_col: &LabelsCol,
+    group_col: &LabelsCol,
+) -> bool {
+    let (LabelsCol::Index(s), LabelsCol::Index(g)) = (sample_col, group_col) else {
+        return false;
+    };
+    let is = |col: usize, names: &[&str]| {
+        row.get(col).is_some_and(|v| {
+            names.contains(&v.trim_start_matches('#').to_ascii_lowercase().as_str())
+        })
+    };
+    is(
+        *s,
+        &["sample", "samples", "sample_id", "sampleid", "iid", "id"],
+    ) && is(
+        *g,
+        &[
+            "group",
+            "groups",
+            "population",
+            "pop",
+            "superpopulation",
+            "label",
+            "labels",
+        ],
+    )
+}
+
+/// Genomic interval, 0-based with inclusive end (`None` runs to the contig end)
+#[derive(Debug, Clone)]
+struct Region {
+    contig: String,
+    start: u64,
+    end: Option<u64>,
+}
+
+impl Region {
+    /// Parse `chr`, `chr:pos` (that one position, as in bcftools), `chr:start-` (to the
+    /// contig end) or `chr:start-end`, with 1-based inclusive coordinates
+    fn parse(s: &str) -> Result<Self> {
+        let bad = || {
+            anyhow::anyhow!(
+                "invalid region {s:?}, expected chr, chr:pos, chr:start- or chr:start-end"
+            )
+        };
+        let (contig, range) = match s.rsplit_once(':') {
+            Some((contig, range)) => (contig, Some(range)),
+            None => (s, None),
+        };
+        let (start, end) = match range {
+            None => (0, None),
+            Some(range) => {
+                let (start, end) = match range.split_once('-') {
+                    Some((start, "")) => (start.parse::<u64>().map_err(|_| bad())?, None),
+                    Some((start, end)) => (
+                        start.parse::<u64>().map_err(|_| bad())?,
+                        Some(end.parse::<u64>().map_err(|_| bad())?),
+                    ),
+                    None => {
+                        let pos = range.parse::<u64>().map_err(|_| bad())?;
+                        (pos, Some(pos))
                     }
+                };
+                if start == 0 || end.is_some_and(|end| end < start) {
+                    return Err(bad());
+                }
+                (start - 1, end.map(|end| end - 1))
+            }
+        };
+        if contig.is_empty() {
+            return Err(bad());
+        }
+        Ok(Region {
+            contig: contig.to_string(),
+            start,
+            end,
+        })
+    }
+
+    /// Read a BED file (0-based, half-open) into regions
+    fn from_bed(path: &str) -> Result<Vec<Self>> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {path}"))?;
+        let mut regions = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.is_empty()
+                || line.starts_with('#')
+                || line.starts_with("track")
+                || line.starts_with("browser")
+            {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            let bad = || anyhow::anyhow!("{path}:{}: expected <chrom> <start> <end>", i + 1);
+            let contig = fields.next().ok_or_else(bad)?;
+            let start: u64 = fields.next().ok_or_else(bad)?.parse().map_err(|_| bad())?;
+            let end: u64 = fields.next().ok_or_else(bad)?.parse().map_err(|_| bad())?;
+            if end <= start {
+                continue;
+            }
+            regions.push(Region {
+                contig: contig.to_string(),
+                start,
+                end: Some(end - 1),
+            });
+        }
+        Ok(regions)
+    }
+
+    /// Regions from repeated/comma-separated `chr:start-end` options plus an optional BED file
+    fn from_opts(specs: &[String], bed: Option<&str>) -> Result<Vec<Self>> {
+        let mut regions = specs
+            .iter()
+            .map(|r| Region::parse(r))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(path) = bed {
+            regions.extend(Region::from_bed(path)?);
+        }
+        Ok(regions)
+    }
+}
+
+/// Sort `(contig, start, end)` intervals and merge the overlapping or adjacent ones
+fn merge_regions<K: Ord>(mut regions: Vec<(K, u64, Option<u64>)>) -> Vec<(K, u64, Option<u64>)> {
+    regions.sort();
+    let mut merged: Vec<(K, u64, Option<u64>)> = Vec::new();
+    for (contig, start, end) in regions {
+        match merged.last_mut() {
+            Some((last_contig, _, last_end))
+                if *last_contig == contig && last_end.is_none_or(|e| start <= e + 1) =>
+            {
+                *last_end = match (*last_end, end) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    _ => None,
+                };
+            }
+            _ => merged.push((contig, start, end)),
+        }
+    }
+    merged
+}
+
+/// Regions checked against each streamed record, for --targets/--targets-file and --par-regions
+struct RegionSet {
+    // per contig, sorted and non-overlapping (start, end)
+    by_contig: HashMap<String, Vec<(u64, Option<u64>)>>,
+}
+
+impl RegionSet {
+    fn new(regions: &[Region]) -> Self {
+        let mut by_contig: HashMap<String, Vec<(u64, Option<u64>)>> = HashMap::new();
+        let merged = merge_regions(
+            regions
+                .iter()
+                .map(|r| (r.contig.clone(), r.start, r.end))
+                .collect(),
+        );
+        for (contig, start, end) in merged {
+            by_contig.entry(contig).or_default().push((start, end));
+        }
+        RegionSet { by_contig }
+    }
+
+    /// Whether the 0-based inclusive span `start..=end` on `contig` touches a target
+    fn overlaps(&self, contig: &str, start: u64, end: u64) -> bool {
+        let Some(intervals) = self.by_contig.get(contig) else {
+            return false;
+        };
+        // the last interval starting at or before `end` is the only candidate
+        let idx = intervals.partition_point(|&(s, _)| s <= end);
+        idx > 0 && intervals[idx - 1].1.is_none_or(|e| e >= start)
+    }
+}
+
+/// Name of the contig of `rec`
+fn record_contig(rec: &bcf::Record, header: &bcf::header::HeaderView) -> Result<String> {
+    let rid = rec.rid().context("record has no contig")?;
+    Ok(String::from_utf8_lossy(header.rid2name(rid)?).into_owned())
+}
+
+/// Whether one of the record's FILTER ids is in `allowed`, `.` standing for an unset FILTER
+fn filter_allowed(rec: &bcf::Record, header: &bcf::header::HeaderView, allowed: &[String]) -> bool {
+    let mut names = rec
+        .filters()
+        .map(|id| String::from_utf8_lossy(&header.id_to_name(id)).into_owned())
+        .peekable();
+    if names.peek().is_none() {
+        return allowed.iter().any(|f| f == ".");
+    }
+    names.any(|name| allowed.contains(&name))
+}
+
+/// Comparison of an --exclude test
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CmpOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+/// An --exclude test: `QUAL` or `INFO/<tag>`, a comparison and a number
+#[derive(Debug, Clone)]
+struct Exclude {
+    /// None for QUAL
+    info: Option<String>,
+    op: CmpOp,
+    value: f64,
+}
+
+impl Exclude {
+    fn parse(s: &str) -> std::result::Result<Self, String> {
+        // two-character operators first, so `<=` isn't taken for `<`
+        let ops = [
+            ("<=", CmpOp::Le),
+            (">=", CmpOp::Ge),
+            ("==", CmpOp::Eq),
+            ("!=", CmpOp::Ne),
+            ("<", CmpOp::Lt),
+            (">", CmpOp::Gt),
+            ("=", CmpOp::Eq),
+        ];
+        let (field, op, value) = ops
+            .iter()
+            .find_map(|(sym, op)| s.split_once(sym).map(|(f, v)| (f.trim(), *op, v.trim())))
+            .ok_or_else(|| format!("expected e.g. QUAL<30 or INFO/DP<100, got {s}"))?;
+        let info = match field {
+            "QUAL" => None,
+            _ => match field.strip_prefix("INFO/") {
+                Some(tag) if !tag.is_empty() => Some(tag.to_string()),
+                _ => return Err(format!("expected QUAL or INFO/<tag>, got {field}")),
+            },
+        };
+        let value = value
+            .parse()
+            .map_err(|_| format!("expected a number after the comparison, got {value}"))?;
+        Ok(Exclude { info, op, value })
+    }
+
+    /// Whether the record matches: QUAL, or any value of the INFO field, passes the test.
+    /// A missing QUAL or INFO field never matches
+    fn matches(&self, rec: &bcf::Record) -> bool {
+        let values: Vec<f64> = match &self.info {
+            None => {
+                let qual = rec.qual();
+                if qual.is_missing() {
+                    return false;
+                }
+                vec![qual as f64]
+            }
+            Some(tag) => {
+                if let Ok(Some(v)) = rec.info(tag.as_bytes()).float() {
+                    v.iter()
+                        .filter(|x| !x.is_missing())
+                        .map(|&x| x as f64)
+                        .collect()
+                } else if let Ok(Some(v)) = rec.info(tag.as_bytes()).integer() {
+                    v.iter()
+                        .filter(|x| !x.is_missing())
+                        .map(|&x| x as f64)
+                        .collect()
                 } else {
-                    // diploid
-                    let a0 = alleles[0];
-                    let a1 = alleles[1];
-                    for a in [a0, a1] {
-                        if let Some(x) = a {
-                            st.an += 1;
-                            st.ac[x as usize] += 1;
+                    Vec::new()
+                }
+            }
+        };
+        values.iter().any(|&x| match self.op {
+            CmpOp::Lt => x < self.value,
+            CmpOp::Le => x <= self.value,
+            CmpOp::Gt => x > self.value,
+            CmpOp::Ge => x >= self.value,
+            CmpOp::Eq => x == self.value,
+            CmpOp::Ne => x != self.value,
+        })
+    }
+}
+
+/// Samples to treat as haploid on the non-PAR part of chrX, from --sex-file
+struct SexModel {
+    male_mask: Vec<bool>,
+    female_mask: Vec<bool>,
+    x_contigs: Vec<String>,
+    y_contigs: Vec<String>,
+    par: RegionSet,
+}
+
+/// Part of the genome a record is on, as far as the sex model is concerned
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum SexRegion {
+    /// autosomes and the pseudo-autosomal regions: everyone is diploid
+    Diploid,
+    /// chrX outside the PARs: males are haploid
+    X,
+    /// chrY outside the PARs: males are haploid, females have no call
+    Y,
+}
+
+impl SexModel {
+    /// Read `<sample> <sex>` lines; M/male/1 are males, F/female/2 females, anything else unknown
+    fn read_sex_file(path: &str) -> Result<HashMap<String, bool>> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {path}"))?;
+        let mut is_male = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(sample), Some(sex)) = (fields.next(), fields.next()) else {
+                anyhow::bail!("{path}:{}: expected <sample> <sex>", i + 1);
+            };
+            match sex.to_ascii_lowercase().as_str() {
+                "m" | "male" | "1" => is_male.insert(sample.to_string(), true),
+                "f" | "female" | "2" => is_male.insert(sample.to_string(), false),
+                _ => None,
+            };
+        }
+        Ok(is_male)
+    }
+
+    /// Region of a record spanning `start..=end` (0-based) on `contig`
+    fn region(&self, contig: &str, start: u64, end: u64) -> SexRegion {
+        if self.par.overlaps(contig, start, end) {
+            SexRegion::Diploid
+        } else if self.x_contigs.iter().any(|x| x == contig) {
+            SexRegion::X
+        } else if self.y_contigs.iter().any(|y| y == contig) {
+            SexRegion::Y
+        } else {
+            SexRegion::Diploid
+        }
+    }
+}
+
+/// One call of a haploid sample that the VCF may have written as diploid: `1/1` becomes `1`
+/// and a heterozygous call, impossible on a haploid sample, becomes missing
+fn to_haploid(gt: &Gt) -> Gt {
+    let mut called = gt.iter().flatten();
+    match called.next() {
+        Some(&first) if called.all(|&a| a == first) => vec![Some(first)],
+        _ => vec![None],
+    }
+}
+
+/// Input VCF, either streamed in full or fetched region by region through its index
+enum Input {
+    Full(BcfReader),
+    Regions {
+        reader: IndexedReader,
+        // (rid, start, end), sorted and merged
+        regions: Vec<(u32, u64, Option<u64>)>,
+        next: usize,
+    },
+}
+
+/// `path` as a URL when it names a remote file (`https://...`, `s3://...`)
+fn remote_url(path: &str) -> Option<url::Url> {
+    // a bare `C:\...` parses as a URL with scheme `c`
+    url::Url::parse(path)
+        .ok()
+        .filter(|url| url.scheme().len() > 1 && url.scheme() != "file")
+}
+
+impl Input {
+    fn open(path: &str, regions: &[Region]) -> Result<Self> {
+        if path == "-" {
+            if !regions.is_empty() {
+                anyhow::bail!("--regions/--regions-file need an indexed file and can't be used when reading from stdin");
+            }
+            let reader = BcfReader::from_stdin().context("reading input VCF from stdin")?;
+            return Ok(Input::Full(reader));
+        }
+        let url = remote_url(path);
+        let remote_context = || {
+            format!(
+                "opening input VCF {path} (remote input needs htslib built with libcurl; \
+                 s3:// and gs:// also need the s3/gcs features of vcfgrpaf)"
+            )
+        };
+        if regions.is_empty() {
+            let reader = match &url {
+                Some(url) => BcfReader::from_url(url).with_context(remote_context)?,
+                None => BcfReader::from_path(path)
+                    .with_context(|| format!("opening input VCF {path}"))?,
+            };
+            return Ok(Input::Full(reader));
+        }
+
+        // htslib fetches the index of a remote file itself
+        if url.is_none()
+            && !["csi", "tbi"]
+                .iter()
+                .any(|ext| std::path::Path::new(&format!("{path}.{ext}")).exists())
+        {
+            anyhow::bail!(
+                "--regions/--regions-file need an indexed input, but {path}.csi/.tbi was not found"
+            );
+        }
+        let reader = match &url {
+            Some(url) => IndexedReader::from_url(url).with_context(remote_context)?,
+            None => IndexedReader::from_path(path)
+                .with_context(|| format!("opening indexed input VCF {path}"))?,
+        };
+
+        let mut resolved = Vec::new();
+        for r in regions {
+            let rid = reader
+                .header()
+                .name2rid(r.contig.as_bytes())
+                .with_context(|| format!("contig {} is not in the VCF header", r.contig))?;
+            resolved.push((rid, r.start, r.end));
+        }
+        Ok(Input::Regions {
+            reader,
+            regions: merge_regions(resolved),
+            next: 0,
+        })
+    }
+
+    /// Decompress in `n` threads
+    fn set_threads(&mut self, n: usize) -> Result<()> {
+        match self {
+            Input::Full(reader) => reader.set_threads(n)?,
+            Input::Regions { reader, .. } => reader.set_threads(n)?,
+        }
+        Ok(())
+    }
+
+    fn header(&self) -> &bcf::header::HeaderView {
+        match self {
+            Input::Full(reader) => reader.header(),
+            Input::Regions { reader, .. } => reader.header(),
+        }
+    }
+
+    fn empty_record(&self) -> bcf::Record {
+        match self {
+            Input::Full(reader) => reader.empty_record(),
+            Input::Regions { reader, .. } => reader.empty_record(),
+        }
+    }
+
+    /// Read the next record, moving on to the next region when one is exhausted
+    fn read(&mut self, rec: &mut bcf::Record) -> Option<Result<()>> {
+        match self {
+            Input::Full(reader) => reader.read(rec).map(|r| r.map_err(Into::into)),
+            Input::Regions {
+                reader,
+                regions,
+                next,
+            } => loop {
+                if *next > 0 {
+                    // a record spanning into this region from the previous one was read there
+                    let rid = regions[*next - 1].0;
+                    let seen_until = match next.checked_sub(2).map(|prev| regions[prev]) {
+                        Some((prev_rid, _, Some(prev_end))) if prev_rid == rid => Some(prev_end),
+                        _ => None,
+                    };
+                    match reader.read(rec) {
+                        Some(Ok(())) if seen_until.is_some_and(|end| rec.pos() as u64 <= end) => {
+                            continue
                         }
+                        Some(r) => return Some(r.map_err(Into::into)),
+                        None => {}
                     }
-                    match (a0, a1) {
-                        (None, None) => st.n_miss += 1,
-                        (Some(x), Some(y)) if x == y && x == 0 => st.n_homref += 1,
-                        (Some(x), Some(y)) if x == y && x == 1 => st.n_homalt += 1,
-                        (Some(_), Some(_)) => st.n_het += 1,
-                        _ => st.n_hemi += 1, // one missing
+                }
+                let &(rid, start, end) = regions.get(*next)?;
+                *next += 1;
+                if let Err(e) = reader.fetch(rid, start, end) {
+                    return Some(Err(e.into()));
+                }
+            },
+        }
+    }
+}
+
+const DEFAULT_NAME_TEMPLATE: &str = "{stat}_{group}";
+
+/// INFO id for a statistic: `<tag>_<grp>` or as --name-template, or the bare tag for the
+/// whole cohort
+fn info_id(tag: &str, grp: Option<&str>, opts: &Opts) -> String {
+    let tag = opts.naming.tag_name(tag);
+    match grp {
+        Some(grp) => opts
+            .name_template
+            .replace("{stat}", tag)
+            .replace("{group}", grp),
+        None => tag.to_string(),
+    }
+}
+
+/// Whether `id` is a legal VCF INFO id
+fn valid_info_id(id: &str) -> bool {
+    let mut chars = id.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Declare one INFO line, unless the input header already has that id
+fn push_info_line(
+    out_hdr: &mut Header,
+    existing: &HashSet<String>,
+    id: &str,
+    num: &str,
+    typ: &str,
+    desc: &str,
+) {
+    if !existing.contains(id) {
+        let line = format!("##INFO=<ID={id},Number={num},Type={typ},Description=\"{desc}\">");
+        out_hdr.push_record(line.as_bytes());
+    }
+}
+
+/// Declare the INFO lines of `Opts::tags` for one group
+fn add_info_lines(
+    out_hdr: &mut Header,
+    grp: Option<&str>,
+    samples_desc: &str,
+    tags: &[&str],
+    opts: &Opts,
+    existing: &HashSet<String>,
+) {
+    let naming = opts.naming;
+    let mut add_info_line = |id: &str, num: &str, typ: &str, desc: &str| {
+        push_info_line(out_hdr, existing, id, num, typ, desc)
+    };
+    if opts.compact_string {
+        let tags: Vec<&str> = tags.iter().map(|t| naming.tag_name(t)).collect();
+        add_info_line(
+            &info_id("GRPAF", grp, opts),
+            "1",
+            "String",
+            &format!(
+                "Statistics on {samples_desc} samples as TAG:value pairs joined by |, values of several ALT alleles joined by /: {}",
+                tags.join("|")
+            ),
+        );
+        return;
+    }
+
+    for t in tags {
+        let name = naming.tag_name(t);
+        let (number, typ) = tag_shape(t, opts);
+        let detail = match *t {
+            "N_HET" => {
+                "heterozygous calls carrying each ALT (a 1/2 call counts for both ALTs)".to_string()
+            }
+            "N_HOMALT" => "calls homozygous for each ALT".to_string(),
+            "HWE" => "two-sided exact test p-value from the diploid calls".to_string(),
+            "ExcHet" => {
+                "one-sided exact test probability of excess heterozygosity from the diploid calls"
+                    .to_string()
+            }
+            "AC_Het" => "copies of each ALT in heterozygous calls".to_string(),
+            "AC_Hom" => "copies of each ALT in homozygous calls".to_string(),
+            "InbreedingCoeff" => {
+                "1 - observed/expected heterozygous diploid calls of each ALT".to_string()
+            }
+            "SINGLETON" => "set when an ALT has AC 1".to_string(),
+            "DOUBLETON" => "set when an ALT has AC 2".to_string(),
+            "N_SINGLETON" => "1 for each ALT with AC 1, else 0".to_string(),
+            "N_DOUBLETON" => "1 for each ALT with AC 2, else 0".to_string(),
+            "RARE" => format!(
+                "set when an ALT has AC 1 to {}",
+                opts.rare_threshold.unwrap_or(0)
+            ),
+            "HET_AB" => "mean allele balance (reads of the higher allele over both, from FORMAT/AD) of the heterozygous calls".to_string(),
+            "N_PHASED" => "heterozygous calls that are phased".to_string(),
+            "N_DIPLOID" => {
+                "fully called diploid calls, the ones HWE, ExcHet and InbreedingCoeff use"
+                    .to_string()
+            }
+            "N_CALLED" => "samples with at least one called allele".to_string(),
+            "N_PARTIAL" => {
+                "calls with some but not all alleles missing, such as 0/.".to_string()
+            }
+            "F_MISSING" => "fraction of samples with a missing genotype".to_string(),
+            _ => String::new(),
+        };
+        let desc = if detail.is_empty() {
+            format!("{name} on {samples_desc} samples")
+        } else {
+            format!("{name} on {samples_desc} samples, {detail}")
+        };
+        add_info_line(&info_id(t, grp, opts), number, type_name(typ), &desc);
+    }
+}
+
+/// VCF Number and Type of the INFO tag of a statistic. The header lines, the values of
+/// `tag_value` and the per-ALT columns of --format tsv/jsonl all follow it
+fn tag_shape(tag: &str, opts: &Opts) -> (&'static str, TagType) {
+    match tag {
+        "AC" if opts.ac_number == AcNumber::R => ("R", TagType::Integer),
+        "AC" | "MAC" | "N_HET" | "N_HOMALT" | "AC_Het" | "AC_Hom" | "N_SINGLETON"
+        | "N_DOUBLETON" => ("A", TagType::Integer),
+        "AF" | "MAF" | "InbreedingCoeff" => ("A", TagType::Float),
+        "HWE" | "ExcHet" | "F_MISSING" | "HET_AB" => ("1", TagType::Float),
+        "SINGLETON" | "DOUBLETON" | "RARE" => ("0", TagType::Flag),
+        _ => ("1", TagType::Integer),
+    }
+}
+
+/// Index of the first ALT in the values of a tag with one value per ALT, None otherwise
+fn first_alt(tag: &str, opts: &Opts) -> Option<usize> {
+    match tag_shape(tag, opts).0 {
+        "A" => Some(0),
+        "R" => Some(1),
+        _ => None,
+    }
+}
+
+/// Type of a header line
+fn type_name(typ: TagType) -> &'static str {
+    match typ {
+        TagType::Flag => "Flag",
+        TagType::Integer => "Integer",
+        TagType::Float => "Float",
+        TagType::String => "String",
+    }
+}
+
+/// Index a written VCF/BCF the way `bcftools index` does: CSI for BCF, TBI for VCF
+fn build_index(path: &str, is_bcf: bool, n_threads: usize) -> Result<()> {
+    let c_path = std::ffi::CString::new(path)?;
+    // a min_shift of 0 asks for a TBI, which BCF can't have
+    let min_shift = if is_bcf { 14 } else { 0 };
+    // SAFETY: c_path outlives the call, and a null index path makes htslib name the index
+    let ret = unsafe {
+        rust_htslib::htslib::bcf_index_build3(
+            c_path.as_ptr(),
+            std::ptr::null(),
+            min_shift,
+            n_threads as i32,
+        )
+    };
+    match ret {
+        0 => Ok(()),
+        -3 => anyhow::bail!("{path} is not a bgzipped VCF or a BCF, can't index it"),
+        _ => anyhow::bail!("failed to index {path} (htslib error {ret})"),
+    }
+}
+
+/// Records in a local VCF/BCF from the per-contig counts of its .csi/.tbi index; None
+/// without an index, or with one that has no counts
+fn indexed_record_count(path: &str, n_contigs: u32) -> Option<u64> {
+    let has_index = ["csi", "tbi"]
+        .iter()
+        .any(|ext| std::path::Path::new(&format!("{path}.{ext}")).exists());
+    if remote_url(path).is_some() || !has_index {
+        return None;
+    }
+    let c_path = std::ffi::CString::new(path).ok()?;
+    // SAFETY: c_path outlives the load, and the index is freed once, after its last use
+    unsafe {
+        // looks for a .csi first, then the .tbi
+        let idx = rust_htslib::htslib::hts_idx_load(
+            c_path.as_ptr(),
+            rust_htslib::htslib::HTS_FMT_TBI as i32,
+        );
+        if idx.is_null() {
+            return None;
+        }
+        let mut total = None;
+        // a TBI numbers its own contigs, but never has more than the header
+        for tid in 0..n_contigs as i32 {
+            let (mut mapped, mut unmapped) = (0, 0);
+            if rust_htslib::htslib::hts_idx_get_stat(idx, tid, &mut mapped, &mut unmapped) == 0 {
+                *total.get_or_insert(0) += mapped;
+            }
+        }
+        rust_htslib::htslib::hts_idx_destroy(idx);
+        total
+    }
+}
+
+/// Alleles of every sample, and whether its genotype is phased (`0|1`); an allele index
+/// past the record's `n_allele` alleles is missing, as for the library (see [`Gt`])
+fn decode_gts(
+    gts: &bcf::record::Genotypes<'_, Buffer>,
+    n_samples: usize,
+    n_allele: usize,
+) -> (Vec<Gt>, Vec<bool>) {
+    let mut all_gts = Vec::with_capacity(n_samples);
+    let mut all_phased = Vec::with_capacity(n_samples);
+    for samp_idx in 0..n_samples {
+        let gt = gts.get(samp_idx);
+        // htslib keeps the phase on the alleles after the first
+        let phased = gt.iter().skip(1).any(|a| {
+            matches!(
+                a,
+                bcf::record::GenotypeAllele::Phased(_) | bcf::record::GenotypeAllele::PhasedMissing
+            )
+        });
+        // filtered here too, so FORMAT/AD and the other per-allele fields are never
+        // indexed past the record's alleles
+        let alleles: Gt = gt
+            .iter()
+            .map(|a| a.index().filter(|&a| (a as usize) < n_allele))
+            .collect();
+        all_gts.push(alleles);
+        all_phased.push(phased);
+    }
+    (all_gts, all_phased)
+}
+
+/// Remove an INFO field from the record, whatever type it is declared with
+fn clear_info(rec: &mut bcf::Record, tag: &[u8]) -> Result<()> {
+    let (tag_type, _) = rec.header().info_type(tag)?;
+    match tag_type {
+        TagType::Flag => rec.clear_info_flag(tag)?,
+        TagType::Integer => rec.clear_info_integer(tag)?,
+        TagType::Float => rec.clear_info_float(tag)?,
+        TagType::String => rec.clear_info_string(tag)?,
+    }
+    Ok(())
+}
+
+/// Value of one statistic of `Opts::tags`, as written to INFO
+enum TagValue {
+    Int(Vec<i32>),
+    Float(Vec<f32>),
+    Flag(bool),
+}
+
+/// Value of `tag`, typed after `tag_shape` so the values can't disagree with the header
+fn tag_value(tag: &str, stats: &AfStats, opts: &Opts) -> TagValue {
+    let typ = tag_shape(tag, opts).1;
+    if typ == TagType::Flag {
+        // any ALT, counted within the group
+        let threshold = match tag {
+            "SINGLETON" => 1..=1,
+            "DOUBLETON" => 2..=2,
+            "RARE" => 1..=opts.rare_threshold.unwrap_or(0),
+            _ => unreachable!("unknown flag {tag}"),
+        };
+        return TagValue::Flag(stats.ac.iter().skip(1).any(|ac| threshold.contains(ac)));
+    }
+    let counts = |v: &[u32]| v.iter().map(|&x| Some(x as f64)).collect();
+    // frequencies without (enough) called alleles are left missing, counts are
+    // still written
+    let freq = |v: &[f64]| {
+        if stats.an == 0 || !stats.passes(opts.min_an, opts.min_call_rate) {
+            vec![None; v.len()]
+        } else {
+            v.iter().map(|&x| Some(x)).collect()
+        }
+    };
+    let mut values: Vec<Option<f64>> = match tag {
+        "AC" if opts.ac_number == AcNumber::R => counts(&stats.ac),
+        "AC" => counts(&stats.ac[1..]),
+        "MAC" => counts(&stats.mac),
+        "AN" => counts(&[stats.an]),
+        "N_HEMI" => counts(&[stats.n_hemi]),
+        "N_PARTIAL" => counts(&[stats.n_partial]),
+        "N_MISS" => counts(&[stats.n_miss]),
+        "N_PHASED" => counts(&[stats.n_phased]),
+        "N_DIPLOID" => counts(&[stats.n_diploid]),
+        "HET_AB" => vec![stats.het_ab],
+        // a partially missing call such as `./1` is called (and counted in N_PARTIAL)
+        "N_CALLED" => counts(&[stats.n_samples() - stats.n_miss]),
+        "N_HOMREF" => counts(&[stats.n_homref]),
+        "N_HET" => counts(&stats.n_het_alt),
+        "N_HOMALT" => counts(&stats.n_homalt_alt),
+        "AC_Het" => counts(&stats.ac_het),
+        "AC_Hom" => counts(&stats.ac_hom),
+        // per ALT, so summing over the records counts the singleton (doubleton) alleles
+        "N_SINGLETON" | "N_DOUBLETON" => {
+            let n = if tag == "N_SINGLETON" { 1 } else { 2 };
+            counts(
+                &stats.ac[1..]
+                    .iter()
+                    .map(|&ac| u32::from(ac == n))
+                    .collect::<Vec<_>>(),
+            )
+        }
+        "InbreedingCoeff" => stats.inbreeding.clone(),
+        "AF" => freq(&stats.af),
+        "MAF" => freq(&stats.maf),
+        "HWE" => vec![stats.hwe],
+        "ExcHet" => vec![stats.exc_het],
+        // a group without samples has nothing to be missing
+        "F_MISSING" => vec![match stats.n_samples() {
+            0 => None,
+            n => Some(stats.n_miss as f64 / n as f64),
+        }],
+        _ => unreachable!("unknown statistic {tag}"),
+    };
+    // a group without samples in the VCF (--empty-group-action missing) has no
+    // statistics at all, not zero counts
+    if stats.n_samples() == 0 {
+        values.fill(None);
+    }
+    match typ {
+        TagType::Integer => TagValue::Int(
+            values
+                .iter()
+                .map(|v| v.map_or(i32::missing(), |x| x as i32))
+                .collect(),
+        ),
+        _ => TagValue::Float(
+            values
+                .iter()
+                .map(|v| v.map_or(f32::missing(), |x| x as f32))
+                .collect(),
+        ),
+    }
+}
+
+/// Write the `Opts::tags` values of one group into the record
+fn push_stats(
+    rec: &mut bcf::Record,
+    ids: &[String],
+    stats: &AfStats,
+    tags: &[&str],
+    opts: &Opts,
+    skipped: &HashSet<String>,
+) -> Result<()> {
+    if opts.compact_string {
+        let id = &ids[0];
+        if !skipped.contains(id) {
+            let value = compact_string(stats, tags, opts);
+            rec.push_info_string(id.as_bytes(), &[value.as_bytes()])?;
+        }
+        return Ok(());
+    }
+    for (tag, full) in tags.iter().zip(ids) {
+        if skipped.contains(full) {
+            continue;
+        }
+        match tag_value(tag, stats, opts) {
+            TagValue::Int(v) => rec.push_info_integer(full.as_bytes(), &v)?,
+            TagValue::Float(v) => rec.push_info_float(full.as_bytes(), &v)?,
+            TagValue::Flag(true) => rec.push_info_flag(full.as_bytes())?,
+            TagValue::Flag(false) => {}
+        }
+    }
+    Ok(())
+}
+
+/// `AF:0.5|AC:3|AN:6` value of --compact-string; `;`, `=` and `,` can't be used in an
+/// INFO value, so pairs are joined by `|` and per-ALT values by `/`
+fn compact_string(stats: &AfStats, tags: &[&str], opts: &Opts) -> String {
+    tags.iter()
+        .map(|tag| {
+            let value = match tag_value(tag, stats, opts) {
+                TagValue::Int(v) => v
+                    .iter()
+                    .map(|x| {
+                        if x.is_missing() {
+                            ".".to_string()
+                        } else {
+                            x.to_string()
+                        }
+                    })
+                    .collect::<Vec<_>>(),
+                TagValue::Float(v) => v
+                    .iter()
+                    .map(|&x| {
+                        if x.is_missing() {
+                            ".".to_string()
+                        } else {
+                            format_g(x)
+                        }
+                    })
+                    .collect(),
+                TagValue::Flag(set) => vec![(set as u8).to_string()],
+            };
+            format!("{}:{}", opts.naming.tag_name(tag), value.join("/"))
+        })
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// `x` with 6 significant digits like C's `%g`, which htslib writes INFO floats with
+fn format_g(x: f32) -> String {
+    let trim = |s: String| {
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.').to_string()
+        } else {
+            s
+        }
+    };
+    if x == 0.0 || !x.is_finite() {
+        return x.to_string();
+    }
+    let exp = x.abs().log10().floor() as i32;
+    if !(-4..6).contains(&exp) {
+        let s = format!("{x:.5e}");
+        let (mantissa, exp) = s.split_once('e').unwrap_or((&s, "0"));
+        format!("{}e{exp}", trim(mantissa.to_string()))
+    } else {
+        trim(format!("{x:.*}", (5 - exp) as usize))
+    }
+}
+
+/// Header row of `--format tsv`, with the same names as the INFO tags
+fn tsv_header(
+    out: &mut impl Write,
+    suffixes: &[Option<&str>],
+    tags: &[&str],
+    opts: &Opts,
+) -> Result<()> {
+    let mut cols: Vec<String> = ["CHROM", "POS", "ID", "REF", "ALT"]
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+    for grp in suffixes {
+        cols.extend(tags.iter().map(|tag| info_id(tag, *grp, opts)));
+    }
+    writeln!(out, "{}", cols.join("\t"))?;
+    Ok(())
+}
+
+/// Rows of `--format tsv` for one record, one per ALT allele
+fn write_tsv_rows(
+    out: &mut impl Write,
+    chrom: &str,
+    rec: &bcf::Record,
+    stats: &[(Option<&str>, &AfStats)],
+    tags: &[&str],
+    opts: &Opts,
+) -> Result<()> {
+    let id = String::from_utf8_lossy(&rec.id()).into_owned();
+    let alleles = rec.alleles();
+    let ref_allele = String::from_utf8_lossy(alleles[0]);
+    let values: Vec<Vec<(TagValue, Option<usize>)>> = stats
+        .iter()
+        .map(|(_, st)| {
+            tags.iter()
+                .map(|tag| (tag_value(tag, st, opts), first_alt(tag, opts)))
+                .collect()
+        })
+        .collect();
+    // a site without ALT still gets a row, "." for the per-ALT columns
+    let n_alt = alleles.len() - 1;
+    for i in 0..n_alt.max(1) {
+        let alt = alleles
+            .get(i + 1)
+            .map_or("".into(), |a| String::from_utf8_lossy(a));
+        let mut row = vec![
+            chrom.to_string(),
+            (rec.pos() + 1).to_string(),
+            id.clone(),
+            ref_allele.to_string(),
+            if alt.is_empty() {
+                ".".into()
+            } else {
+                alt.to_string()
+            },
+        ];
+        for (value, first_alt) in values.iter().flatten() {
+            let j = first_alt.map_or(0, |first| first + i);
+            row.push(match value {
+                TagValue::Int(v) => v
+                    .get(j)
+                    .filter(|x| !x.is_missing())
+                    .map_or(".".into(), |x| x.to_string()),
+                // as written to INFO, so the table matches the VCF
+                TagValue::Float(v) => v
+                    .get(j)
+                    .filter(|x| !x.is_missing())
+                    .map_or(".".into(), |&x| format_g(x)),
+                TagValue::Flag(set) => (*set as u8).to_string(),
+            });
+        }
+        writeln!(out, "{}", row.join("\t"))?;
+    }
+    Ok(())
+}
+
+/// Current UTC time as `YYYY-MM-DDTHH:MM:SSZ`
+fn utc_timestamp() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // civil-from-days (H. Hinnant), days counted from 1970-01-01
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Records read before their statistics are computed in parallel
+const BATCH_SIZE: usize = 1024;
+
+/// Statistics of one record, per group and over all samples when asked for
+struct RecordStats<'a> {
+    groups: HashMap<&'a String, AfStats>,
+    total: Option<AfStats>,
+}
+
+fn record_stats<'a>(
+    rec: &bcf::Record,
+    annotator: &Annotator,
+    groups: &'a [String],
+    masks: &HashMap<String, Vec<bool>>,
+    total_mask: Option<&Vec<bool>>,
+    sex: Option<(&SexModel, SexRegion)>,
+    opts: &Opts,
+) -> Result<RecordStats<'a>> {
+    let n_samples = rec.sample_count() as usize;
+    let n_allele = rec.allele_count() as usize;
+    let alleles = rec.alleles();
+    // `*` stands for an allele removed by an upstream deletion
+    let star = alleles.iter().position(|a| *a == b"*").map(|i| i as u32);
+    // symbolic ALTs (<DEL>, <CNV>, ...) are structural calls, not tested for HWE
+    let symbolic = alleles.iter().skip(1).any(|a| a.starts_with(b"<"));
+    // GT is decoded once per record; the per-group work then shares it read-only
+    let (mut all_gts, phased) = decode_gts(&rec.genotypes()?, n_samples, n_allele);
+    let sex = sex.filter(|(_, region)| *region != SexRegion::Diploid);
+    let haploid = sex.map(|(sex, _)| sex.male_mask.as_slice());
+    let absent = sex
+        .filter(|(_, region)| *region == SexRegion::Y)
+        .map(|(sex, _)| sex.female_mask.as_slice());
+    if let Some(haploid) = haploid {
+        for (gt, _) in all_gts.iter_mut().zip(haploid).filter(|(_, &h)| h) {
+            *gt = to_haploid(gt);
+        }
+    }
+    if let Some((tag, value)) = opts.sample_filter() {
+        // a record without the tag has no sample filtered out
+        if let Ok(filters) = rec.format(tag.as_bytes()).string() {
+            for (gt, filter) in all_gts.iter_mut().zip(filters.iter()) {
+                if !matches!(*filter, b"" | b".") && *filter != value.as_bytes() {
+                    gt.iter_mut().for_each(|a| *a = None);
+                }
+            }
+        }
+    }
+    for (tag, min) in [(&b"GQ"[..], opts.min_gq), (b"DP", opts.min_dp)] {
+        let Some(min) = min else { continue };
+        if let Ok(values) = rec.format(tag).integer() {
+            for (gt, v) in all_gts.iter_mut().zip(values.iter()) {
+                // a missing value doesn't fail the threshold
+                if v.first().is_some_and(|&v| !v.is_missing() && v < min) {
+                    gt.iter_mut().for_each(|a| *a = None);
+                }
+            }
+        }
+    }
+    if let Some(star) = star.filter(|_| opts.exclude_star) {
+        for a in all_gts.iter_mut().flatten().filter(|a| **a == Some(star)) {
+            *a = None;
+        }
+    }
+    // reads per allele of each sample; negative values are htslib's missing and vector end
+    let ad: Vec<Vec<Option<u32>>> = match rec.format(b"AD").integer() {
+        Ok(ad) if annotator.config.wants("HET_AB") => ad
+            .iter()
+            .map(|v| v.iter().map(|&x| (x >= 0).then_some(x as u32)).collect())
+            .collect(),
+        _ => Vec::new(),
+    };
+    let stats = |mask: &[bool]| {
+        // samples without this chromosome are left out, not counted as missing
+        let present: Vec<bool>;
+        let mask = match absent {
+            Some(absent) => {
+                present = mask.iter().zip(absent).map(|(&m, &a)| m && !a).collect();
+                &present
+            }
+            None => mask,
+        };
+        let mut st = annotator.group_stats(&all_gts, Some(&phased), n_allele, mask);
+        st.het_ab = het_allele_balance(&all_gts, &ad, mask);
+        if symbolic {
+            st.hwe = None;
+            st.exc_het = None;
+        }
+        st
+    };
+    let groups = groups
+        .par_iter()
+        .map(|grp| (grp, stats(&masks[grp])))
+        .collect();
+    let total = total_mask.map(|mask| stats(mask));
+    Ok(RecordStats { groups, total })
+}
+
+/// Totals of one group over the annotated records, for --summary-out
+#[derive(Default)]
+struct GroupSummary {
+    n_variants: u64,
+    /// Records where the group carries an ALT allele
+    n_polymorphic: u64,
+    /// ALT alleles seen once in the group
+    n_singletons: u64,
+    /// AF of every ALT allele of records the group has alleles at
+    af_sum: f64,
+    n_af: u64,
+    call_rate_sum: f64,
+}
+
+impl GroupSummary {
+    fn add(&mut self, st: &AfStats) {
+        self.n_variants += 1;
+        if st.ac.iter().skip(1).any(|&ac| ac > 0) {
+            self.n_polymorphic += 1;
+        }
+        self.n_singletons += st.ac.iter().skip(1).filter(|&&ac| ac == 1).count() as u64;
+        if st.an > 0 {
+            self.af_sum += st.af.iter().sum::<f64>();
+            self.n_af += st.af.len() as u64;
+        }
+        self.call_rate_sum += st.call_rate();
+    }
+}
+
+/// The --summary-out table, in labels order
+fn write_summary(path: &str, rows: &[(&str, usize, GroupSummary)]) -> Result<()> {
+    let mut out = std::io::BufWriter::new(
+        std::fs::File::create(path).with_context(|| format!("creating {path}"))?,
+    );
+    writeln!(
+        out,
+        "group\tn_samples\tn_variants\tn_polymorphic\tn_singletons\tmean_af\tmean_call_rate"
+    )?;
+    let mean = |sum: f64, n: u64| {
+        if n == 0 {
+            ".".to_string()
+        } else {
+            format_g((sum / n as f64) as f32)
+        }
+    };
+    for (grp, n_samples, sum) in rows {
+        writeln!(
+            out,
+            "{grp}\t{n_samples}\t{}\t{}\t{}\t{}\t{}",
+            sum.n_variants,
+            sum.n_polymorphic,
+            sum.n_singletons,
+            mean(sum.af_sum, sum.n_af),
+            mean(sum.call_rate_sum, sum.n_variants)
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// One line of --stats-json
+#[derive(serde::Serialize)]
+struct SiteStats<'a> {
+    chrom: &'a str,
+    pos: i64, // 1-based
+    #[serde(rename = "ref")]
+    ref_allele: &'a str,
+    alt: Vec<&'a str>,
+    #[serde(serialize_with = "groups_as_map")]
+    groups: Vec<(&'a str, &'a AfStats)>,
+}
+
+/// Keep the groups in labels order rather than going through a sorted map
+fn groups_as_map<S: serde::Serializer, T: serde::Serialize>(
+    groups: &[(&str, T)],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_map(groups.iter().map(|(grp, v)| (grp, v)))
+}
+
+/// One line of `--format jsonl`
+#[derive(serde::Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+struct JsonlRecord<'a> {
+    chrom: &'a str,
+    pos: i64, // 1-based
+    id: &'a str,
+    #[serde(rename = "REF")]
+    ref_allele: &'a str,
+    alt: Vec<&'a str>,
+    #[serde(rename = "groups", serialize_with = "groups_as_map")]
+    groups: Vec<(&'a str, GroupTags<'a>)>,
+}
+
+/// The values of the `tags` of one group, keyed by lower-cased tag; missing values are `null`
+struct GroupTags<'a> {
+    stats: &'a AfStats,
+    tags: &'a [&'static str],
+    opts: &'a Opts,
+}
+
+impl serde::Serialize for GroupTags<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let tags = self.tags;
+        let mut map = serializer.serialize_map(Some(tags.len()))?;
+        for tag in tags {
+            let key = tag.to_lowercase();
+            let per_alt = first_alt(tag, self.opts).is_some();
+            match tag_value(tag, self.stats, self.opts) {
+                TagValue::Int(v) => {
+                    let v: Vec<Option<i32>> =
+                        v.iter().map(|x| (!x.is_missing()).then_some(*x)).collect();
+                    if per_alt {
+                        map.serialize_entry(&key, &v)?
+                    } else {
+                        map.serialize_entry(&key, &v[0])?
+                    }
+                }
+                TagValue::Flag(set) => map.serialize_entry(&key, &set)?,
+                TagValue::Float(v) => {
+                    let v: Vec<Option<f32>> =
+                        v.iter().map(|x| (!x.is_missing()).then_some(*x)).collect();
+                    if per_alt {
+                        map.serialize_entry(&key, &v)?
+                    } else {
+                        map.serialize_entry(&key, &v[0])?
                     }
                 }
             }
         }
+        map.end()
+    }
+}
+
+/// Sends log records to stderr, info as plain lines and the other levels with a prefix
+fn init_logging(opts: &Opts) {
+    let level = match (opts.quiet, opts.verbose) {
+        (true, _) => log::LevelFilter::Warn,
+        (false, 0) => log::LevelFilter::Info,
+        (false, 1) => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .format(|buf, record| match record.level() {
+            log::Level::Info => writeln!(buf, "{}", record.args()),
+            log::Level::Warn => writeln!(buf, "Warning: {}", record.args()),
+            level => writeln!(buf, "{level}: {}", record.args()),
+        })
+        .init();
+}
+
+fn main() -> Result<()> {
+    let opts = Opts::parse();
+    init_logging(&opts);
+    if opts.strict {
+        warn!("--strict is deprecated and does nothing: INFO ids the input already defines are an error unless --force or --skip-existing");
+    }
+    debug!("{opts:?}");
+    if opts.threads > 0 {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(opts.threads)
+            .build_global()
+            .context("setting up the thread pool")?;
+    }
+
+    let config = opts.stats_config()?;
+    let tags = config.tags();
+    if !opts.name_template.contains("{stat}") || !opts.name_template.contains("{group}") {
+        anyhow::bail!(
+            "--name-template needs both {{stat}} and {{group}}, got {}",
+            opts.name_template
+        );
+    }
+    if opts.select_tags.iter().any(|t| t == "RARE") && opts.rare_threshold.is_none() {
+        anyhow::bail!("--tags RARE needs --rare-threshold");
+    }
+    if let Some(filter) = &opts.sample_filter {
+        if opts.sample_filter().is_none() {
+            anyhow::bail!("--sample-filter takes TAG=VALUE, e.g. FT=PASS, got {filter}");
+        }
     }
 
-    if st.an > 0 {
-        st.af = st.ac[1] as f64 / st.an as f64;
-        st.mac = st.ac[0].min(st.ac[1]);
-        st.maf = st.mac as f64 / st.an as f64;
+    // a finished run may have taken hours, so its output is only replaced on request
+    let writes_output = !opts.check && !opts.dry_run && opts.output != "-";
+    if writes_output && !opts.force && std::fs::metadata(&opts.output).is_ok_and(|m| m.is_file()) {
+        anyhow::bail!(
+            "{} already exists; use --force to overwrite it",
+            opts.output
+        );
     }
-    st
-}
-
-fn main() -> Result<()> {
-    let opts = Opts::parse();
 
     // read labels
-    #[derive(serde::Deserialize)]
-    struct Rec {
-        sample: String,
-        group: String,
-    }
-    let mut rdr = ReaderBuilder::new()
-        .delimiter(b'\t')
-        .has_headers(false)
-        .from_path(&opts.labels)
-        .with_context(|| "opening --labels")?;
+    let labels_file = std::fs::File::open(&opts.labels).with_context(|| "opening --labels")?;
+    // bgzip output is a series of gzip members, which MultiGzDecoder reads through
+    let labels_gz = opts.labels.ends_with(".gz") || opts.labels.ends_with(".bgz");
+    let mut labels_reader: Box<dyn std::io::Read> = if labels_gz {
+        Box::new(flate2::read::MultiGzDecoder::new(labels_file))
+    } else {
+        Box::new(labels_file)
+    };
+    let mut labels_text = String::new();
+    labels_reader
+        .read_to_string(&mut labels_text)
+        .with_context(|| {
+            if labels_gz {
+                format!("{} is truncated or not valid gzip", opts.labels)
+            } else {
+                format!("reading {}", opts.labels)
+            }
+        })?;
+    // a .psam without its header line has the .fam columns
+    let psam_header = opts.labels_format == LabelsFormat::Psam
+        && labels_text
+            .lines()
+            .find(|l| !l.trim().is_empty())
+            .is_some_and(|l| l.starts_with('#'));
+    let (delim, sample_col, group_col) = match opts.labels_format {
+        LabelsFormat::Tsv => (
+            match opts.labels_delim {
+                LabelsDelim::Auto => LabelsDelim::sniff(&labels_text),
+                delim => delim,
+            },
+            LabelsCol::Index(0),
+            LabelsCol::Index(1),
+        ),
+        _ if psam_header => (
+            LabelsDelim::Space,
+            LabelsCol::Name("IID".to_string()),
+            LabelsCol::Name("FID".to_string()),
+        ),
+        _ => (LabelsDelim::Space, LabelsCol::Index(1), LabelsCol::Index(0)),
+    };
+    let sample_col = opts.sample_col.clone().unwrap_or(sample_col);
+    let group_col = opts.group_col.clone().unwrap_or(group_col);
+    let mut rows = if delim == LabelsDelim::Space {
+        // any run of blanks separates columns, which the csv reader can't express
+        let mut rows = Vec::new();
+        for (i, line) in labels_text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut row: csv::StringRecord = line.split_whitespace().collect();
+            let mut pos = csv::Position::new();
+            pos.set_line(i as u64 + 1);
+            row.set_position(Some(pos));
+            rows.push(row);
+        }
+        rows
+    } else {
+        ReaderBuilder::new()
+            .delimiter(if delim == LabelsDelim::Comma {
+                b','
+            } else {
+                b'\t'
+            })
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(labels_text.as_bytes())
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .with_context(|| format!("reading {}", opts.labels))?
+    };
+    let by_name =
+        matches!(sample_col, LabelsCol::Name(_)) || matches!(group_col, LabelsCol::Name(_));
+    let has_header = opts.labels_has_header
+        || by_name
+        || psam_header
+        || opts.labels_format == LabelsFormat::Tsv
+            && rows
+                .first()
+                .is_some_and(|row| looks_like_header(row, &sample_col, &group_col));
+    let header = if has_header && !rows.is_empty() {
+        let header = rows.remove(0);
+        if !opts.labels_has_header && !by_name && !psam_header {
+            warn!(
+                "treating the first line of {} as a header: {}",
+                opts.labels,
+                header.iter().collect::<Vec<_>>().join(" ")
+            );
+        }
+        Some(header)
+    } else {
+        None
+    };
+    let sample_col = sample_col.resolve(header.as_ref(), "--sample-col")?;
+    let group_col = group_col.resolve(header.as_ref(), "--group-col")?;
+
+    let renames = match &opts.rename_groups {
+        Some(path) => read_renames(path)?,
+        None => HashMap::new(),
+    };
+    // group name used in the INFO ids -> name in --labels, to catch two ending up the same
+    let mut label_names: HashMap<String, String> = HashMap::new();
     let mut group_map: HashMap<String, Vec<String>> = HashMap::new();
-    for rec in rdr.deserialize::<Rec>() {
-        let rec = rec?;
-        group_map.entry(rec.group).or_default().push(rec.sample);
+    // groups in first-seen order, so header lines and INFO fields are reproducible
+    let mut groups: Vec<String> = Vec::new();
+    for row in &rows {
+        let field = |col: usize| {
+            row.get(col).with_context(|| {
+                let line = row.position().map_or(0, |p| p.line());
+                format!("{}:{line}: no column {}", opts.labels, col + 1)
+            })
+        };
+        let sample = field(sample_col)?.to_string();
+        // `EUR,CASE` puts the sample in both groups
+        for grp in field(group_col)?
+            .split(',')
+            .map(str::trim)
+            .filter(|g| !g.is_empty())
+        {
+            let renamed = renames.get(grp).map_or(grp, String::as_str);
+            let clean = sanitize_group(renamed);
+            match label_names.get(&clean) {
+                Some(label) if label != grp => anyhow::bail!(
+                    "groups {label} and {grp} of {} would both be named {clean}; use --rename-groups",
+                    opts.labels
+                ),
+                Some(_) => {}
+                None => {
+                    if clean != renamed {
+                        warn!(
+                            "group {renamed} is not a valid INFO id suffix, naming it {clean}"
+                        );
+                    }
+                    label_names.insert(clean.clone(), grp.to_string());
+                }
+            }
+            let grp = clean.as_str();
+            let members = group_map.entry(grp.to_string()).or_insert_with(|| {
+                groups.push(grp.to_string());
+                Vec::new()
+            });
+            if !members.contains(&sample) {
+                members.push(sample.clone());
+            }
+        }
+    }
+    if opts.with_total && group_map.contains_key(&opts.total_label) {
+        anyhow::bail!(
+            "--labels already has a group named {0}; pick another --total-label",
+            opts.total_label
+        );
+    }
+    if opts.with_total && opts.complement_group.as_ref() == Some(&opts.total_label) {
+        anyhow::bail!(
+            "--complement-group and --total-label are both {}; pick another name for one of them",
+            opts.total_label
+        );
     }
-    let groups: Vec<String> = group_map.keys().cloned().collect();
-    println!("Loaded {} groups from {}", groups.len(), opts.labels);
+    // stdout may be the output VCF, so every message goes to stderr
+    info!("Loaded {} groups from {}", groups.len(), opts.labels);
 
     // open input VCF
 
-    let mut bcf: BcfReader = BcfReader::from_path(&opts.input).expect("Error opening file.");
-    let mut headerview: bcf::header::HeaderView = bcf.header().clone();
+    let regions = Region::from_opts(&opts.regions, opts.regions_file.as_deref())?;
+    let target_regions = Region::from_opts(&opts.targets, opts.targets_file.as_deref())?;
+    let targets = (!target_regions.is_empty()).then(|| RegionSet::new(&target_regions));
+    if opts.drop_untargeted && targets.is_none() && opts.chroms.is_empty() {
+        anyhow::bail!("--drop-untargeted needs --targets, --targets-file or --chroms");
+    }
+    let mut bcf = Input::open(&opts.input, &regions)?;
+    if opts.io_threads > 1 {
+        bcf.set_threads(opts.io_threads)
+            .context("setting --io-threads on the input")?;
+    }
+    let headerview: bcf::header::HeaderView = bcf.header().clone();
+    // for the percentage and ETA of the progress lines; --regions reads only part of it
+    let n_records = match &bcf {
+        Input::Full(_) if opts.progress_every > 0 => {
+            indexed_record_count(&opts.input, headerview.contig_count())
+        }
+        _ => None,
+    };
+    for chrom in &opts.chroms {
+        if headerview.name2rid(chrom.as_bytes()).is_err() {
+            warn!(
+                "--chroms {chrom} is not a contig of the {} header",
+                opts.input
+            );
+        }
+    }
 
-    let samples: Vec<String> = headerview
-        .samples()
-        .iter()
-        .map(|s| String::from_utf8_lossy(s).into())
-        .collect();
+    // samples() reads through a null pointer when there are none
+    let samples: Vec<String> = if headerview.sample_count() == 0 {
+        Vec::new()
+    } else {
+        headerview
+            .samples()
+            .iter()
+            .map(|s| String::from_utf8_lossy(s).into())
+            .collect()
+    };
+    // rec.genotypes() fails on every record of a sites-only file
+    let sites_only = samples.is_empty() || headerview.format_type(b"GT").is_err();
+    if sites_only {
+        if !opts.allow_sites_only {
+            anyhow::bail!(
+                "{} has no genotypes; nothing to compute (use --allow-sites-only to pass the records through)",
+                opts.input
+            );
+        }
+        warn!(
+            "{} has no genotypes, records are written unchanged",
+            opts.input
+        );
+    }
+    if let Some((tag, _)) = opts.sample_filter() {
+        match headerview.format_type(tag.as_bytes()) {
+            Ok((TagType::String, _)) => {}
+            Ok(_) => anyhow::bail!("--sample-filter: FORMAT/{tag} is not a String"),
+            Err(_) => anyhow::bail!(
+                "--sample-filter: FORMAT/{tag} is not defined in {}",
+                opts.input
+            ),
+        }
+    }
+
+    for tag in opts.exclude.iter().filter_map(|e| e.info.as_ref()) {
+        match headerview.info_type(tag.as_bytes()) {
+            Ok((TagType::Integer | TagType::Float, _)) => {}
+            Ok(_) => anyhow::bail!("--exclude: INFO/{tag} is not a number"),
+            Err(_) => anyhow::bail!("--exclude: INFO/{tag} is not defined in {}", opts.input),
+        }
+    }
 
     // make masks for each group
+    // --samples/--samples-file: VCF samples allowed to count toward any statistic
+    let mut include_list = opts.samples.clone();
+    if let Some(path) = &opts.samples_file {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {path}"))?;
+        include_list.extend(
+            text.lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(String::from),
+        );
+    }
+    let mut include: Option<Vec<bool>> = if include_list.is_empty() {
+        None
+    } else {
+        let set: HashSet<&String> = include_list.iter().collect();
+        let unknown: Vec<&str> = include_list
+            .iter()
+            .filter(|s| !samples.contains(s))
+            .map(|s| s.as_str())
+            .collect();
+        if !unknown.is_empty() {
+            warn!(
+                "{} sample(s) of --samples/--samples-file are not in the VCF: {}",
+                unknown.len(),
+                unknown.join(", ")
+            );
+        }
+        Some(samples.iter().map(|s| set.contains(s)).collect())
+    };
+    if let Some(path) = opts.pedigree.as_ref().filter(|_| opts.founders_only) {
+        let founders = read_pedigree(path)?;
+        let n_unknown = samples
+            .iter()
+            .filter(|s| !founders.contains_key(*s))
+            .count();
+        if n_unknown > 0 {
+            warn!("{n_unknown} VCF sample(s) are not in {path}; they are counted as founders");
+        }
+        let is_founder: Vec<bool> = samples
+            .iter()
+            .map(|s| founders.get(s).copied().unwrap_or(true))
+            .collect();
+        include = Some(match include {
+            Some(inc) => inc.iter().zip(&is_founder).map(|(&a, &b)| a && b).collect(),
+            None => is_founder,
+        });
+    }
+    let is_included = |i: usize| include.as_ref().is_none_or(|inc| inc[i]);
+    let n_counted = (0..samples.len()).filter(|&i| is_included(i)).count();
+
     let mut masks: HashMap<String, Vec<bool>> = HashMap::new();
     for (grp, list) in &group_map {
         let set: HashSet<&String> = list.iter().collect();
         masks.insert(
             grp.clone(),
-            samples.iter().map(|s| set.contains(s)).collect(),
+            samples
+                .iter()
+                .enumerate()
+                .map(|(i, s)| set.contains(s) && is_included(i))
+                .collect(),
+        );
+    }
+    // such a group would otherwise get all-zero AN/AC on every record
+    let empty: Vec<String> = groups
+        .iter()
+        .filter(|grp| !masks[*grp].contains(&true))
+        .cloned()
+        .collect();
+    if !empty.is_empty() && !sites_only {
+        let msg = format!(
+            "{} group(s) have no samples in the VCF{}: {}",
+            empty.len(),
+            if include.is_some() {
+                " after --samples/--samples-file/--founders-only"
+            } else {
+                ""
+            },
+            empty.join(", ")
+        );
+        match opts.empty_group_action {
+            EmptyGroupAction::Error => {
+                anyhow::bail!("{msg} (see --empty-group-action)")
+            }
+            EmptyGroupAction::Skip => {
+                warn!("{msg}; leaving them out of the output");
+                groups.retain(|grp| !empty.contains(grp));
+            }
+            EmptyGroupAction::Missing => {
+                warn!("{msg}; their statistics are written as missing");
+            }
+        }
+    }
+
+    let assigned: HashSet<&String> = group_map.values().flatten().collect();
+    let unassigned: Vec<&str> = samples
+        .iter()
+        .enumerate()
+        .filter(|&(i, s)| !assigned.contains(s) && is_included(i))
+        .map(|(_, s)| s.as_str())
+        .collect();
+    // --complement-group: the counted samples that are in none of the groups
+    let unassigned = match &opts.complement_group {
+        Some(name) => {
+            if group_map.contains_key(name) {
+                anyhow::bail!(
+                    "--labels already has a group named {name}; pick another --complement-group"
+                );
+            }
+            if unassigned.is_empty() {
+                anyhow::bail!(
+                    "--complement-group {name} would be empty: every counted VCF sample is in a group of {}",
+                    opts.labels
+                );
+            }
+            let mask = samples
+                .iter()
+                .enumerate()
+                .map(|(i, s)| !assigned.contains(s) && is_included(i))
+                .collect();
+            masks.insert(name.clone(), mask);
+            group_map.insert(
+                name.clone(),
+                unassigned.iter().map(|s| s.to_string()).collect(),
+            );
+            groups.push(name.clone());
+            Vec::new()
+        }
+        None => unassigned,
+    };
+    let width = groups.iter().map(|g| g.len()).max().unwrap_or(0).max(5);
+    info!("{:<width$}  {:>8}  {:>8}", "group", "labeled", "in VCF");
+    for grp in &groups {
+        let n_in_vcf = masks[grp].iter().filter(|&&m| m).count();
+        info!("{grp:<width$}  {:>8}  {n_in_vcf:>8}", group_map[grp].len());
+    }
+    info!(
+        "{} of {n_counted} VCF samples in no group",
+        unassigned.len()
+    );
+
+    // catch typos in the labels file, they would silently shrink a group
+    let vcf_samples: HashSet<&String> = samples.iter().collect();
+    let mut missing: Vec<&str> = Vec::new();
+    for grp in &groups {
+        for s in &group_map[grp] {
+            if !vcf_samples.contains(s) && !missing.contains(&s.as_str()) {
+                missing.push(s);
+            }
+        }
+    }
+    if !missing.is_empty() && !sites_only {
+        let msg = format!(
+            "{} sample(s) in {} are not in the VCF: {}",
+            missing.len(),
+            opts.labels,
+            missing.join(", ")
+        );
+        if opts.lenient {
+            warn!("{msg}");
+        } else {
+            anyhow::bail!("{msg} (use --lenient to ignore)");
+        }
+    }
+    if !unassigned.is_empty() {
+        let msg = format!(
+            "{} VCF sample(s) are not assigned to any group in {}: {}",
+            unassigned.len(),
+            opts.labels,
+            unassigned.join(", ")
         );
+        if opts.strict_labels {
+            anyhow::bail!("{msg}");
+        } else {
+            warn!("{msg}");
+        }
     }
 
+    // `A,B` group pairs of --fst and --assoc, `A:B` of --compare
+    let group_pairs = |opt: &str, sep: char, pairs: &[String]| -> Result<Vec<(String, String)>> {
+        let mut out = Vec::new();
+        for pair in pairs {
+            let Some((a, b)) = pair.split_once(sep) else {
+                anyhow::bail!("--{opt} takes two groups as A{sep}B, got {pair}");
+            };
+            for grp in [a, b] {
+                if !group_map.contains_key(grp) {
+                    anyhow::bail!("--{opt}: group {grp} is not in {}", opts.labels);
+                }
+                if !groups.iter().any(|g| g == grp) {
+                    anyhow::bail!(
+                        "--{opt}: group {grp} has no samples in the VCF and is skipped by --empty-group-action"
+                    );
+                }
+            }
+            out.push((a.to_string(), b.to_string()));
+        }
+        Ok(out)
+    };
+    let fst_pairs = group_pairs("fst", ',', &opts.fst)?;
+    let assoc_pairs = group_pairs("assoc", ',', &opts.assoc)?;
+    let compare_pairs = group_pairs("compare", ':', &opts.compare)?;
+
+    let sex = match &opts.sex_file {
+        Some(path) => {
+            let is_male = SexModel::read_sex_file(path)?;
+            let unknown: Vec<&str> = samples
+                .iter()
+                .filter(|s| !is_male.contains_key(*s))
+                .map(|s| s.as_str())
+                .collect();
+            if !unknown.is_empty() {
+                warn!(
+                    "{} VCF sample(s) have no known sex in {path} and are treated as diploid on chrX/chrY: {}",
+                    unknown.len(),
+                    unknown.join(", ")
+                );
+            }
+            let par = match &opts.par_regions {
+                Some(bed) => Region::from_bed(bed)?,
+                None => Vec::new(),
+            };
+            Some(SexModel {
+                male_mask: samples
+                    .iter()
+                    .map(|s| is_male.get(s).copied().unwrap_or(false))
+                    .collect(),
+                female_mask: samples
+                    .iter()
+                    .map(|s| is_male.get(s) == Some(&false))
+                    .collect(),
+                x_contigs: match &opts.x_contig {
+                    Some(x) => vec![x.clone()],
+                    None => vec!["X".to_string(), "chrX".to_string()],
+                },
+                y_contigs: match &opts.y_contig {
+                    Some(y) => vec![y.clone()],
+                    None => vec!["Y".to_string(), "chrY".to_string()],
+                },
+                par: RegionSet::new(&par),
+            })
+        }
+        None => None,
+    };
+
+    let writes_total = opts.include_total || opts.with_total;
+    // the whole cohort is kept apart from `masks` so it can't clash with a group name
+    let total_mask = (opts.include_total || opts.with_total || opts.min_ac.is_some())
+        .then(|| include.clone().unwrap_or_else(|| vec![true; samples.len()]));
+
     // inject new header lines
 
-    let want_tags = [
-        "AF", "MAF", "MAC", "AC", "AN", "N_HEMI", "N_MISS", "N_HOMREF", "N_HET", "N_HOMALT",
-    ];
     let all_tags = [
         "ExcHet_",
         "HWE_",
@@ -151,48 +2425,259 @@ fn main() -> Result<()> {
         "AC_",
         "AN_",
         "N_HEMI_",
+        "N_PARTIAL_",
         "N_MISS_",
+        "N_CALLED_",
+        "N_PHASED_",
+        "N_DIPLOID_",
+        "HET_AB_",
         "N_HOMREF_",
         "N_HET_",
         "N_HOMALT_",
+        "F_MISSING_",
+        "nhet_",
+        "nhomalt_",
+        "InbreedingCoeff_",
+        "FST_",
+        "CHISQ_",
+        "P_",
+        "FISHER_",
+        "OR_",
+        "GRPAF_",
+        "SINGLETON_",
+        "DOUBLETON_",
+        "N_SINGLETON_",
+        "N_DOUBLETON_",
+        "RARE_",
     ];
 
-    let mut out_hdr = Header::from_template(bcf.header());
-    
+    let mut out_hdr = if opts.drop_genotypes {
+        // the writer then carries the (empty) sample subset applied by `Writer::subset`
+        Header::from_template_subset(bcf.header(), &[])?
+    } else {
+        Header::from_template(bcf.header())
+    };
 
-    let mut add_info_line = |id: &str, num: &str, typ: &str, desc: &str| {
-        let line = format!("##INFO=<ID={id},Number={num},Type={typ},Description=\"{desc}\">");
-        out_hdr.push_record(line.as_bytes());
+    // INFO suffix of every set of statistics written, in output order
+    let mut stat_suffixes: Vec<Option<&str>> = groups.iter().map(|g| Some(g.as_str())).collect();
+    if opts.include_total {
+        stat_suffixes.push(None);
+    }
+    if opts.with_total {
+        stat_suffixes.push(Some(&opts.total_label));
+    }
+
+    // INFO ids of an earlier run; their definitions are reused rather than declared twice
+    let mut input_ids: HashSet<String> = headerview
+        .header_records()
+        .iter()
+        .filter_map(|record| match record {
+            HeaderRecord::Info { values, .. } => values.get("ID").cloned(),
+            _ => None,
+        })
+        .collect();
+    // --strip-info: every input INFO field goes, so ours are all declared afresh
+    let stripped: Vec<String> = if opts.strip_info {
+        input_ids.drain().collect()
+    } else {
+        Vec::new()
+    };
+    for id in &stripped {
+        out_hdr.remove_info(id.as_bytes());
+    }
+    // INFO ids of each set of statistics, `push_stats` order, resolved once for all records
+    let stat_ids: Vec<Vec<String>> = stat_suffixes
+        .iter()
+        .map(|suffix| {
+            if opts.compact_string {
+                vec![info_id("GRPAF", *suffix, &opts)]
+            } else {
+                tags.iter()
+                    .map(|tag| info_id(tag, *suffix, &opts))
+                    .collect()
+            }
+        })
+        .collect();
+    let mut new_ids: Vec<String> = stat_ids.iter().flatten().cloned().collect();
+    if opts.annotate_nalt {
+        new_ids.push("N_ALT".to_string());
+    }
+    for (a, b) in &fst_pairs {
+        new_ids.push(format!("FST_{a}_{b}"));
+    }
+    for (a, b) in &assoc_pairs {
+        new_ids.push(format!("CHISQ_{a}_{b}"));
+        new_ids.push(format!("P_{a}_{b}"));
+    }
+    for (a, b) in &compare_pairs {
+        new_ids.push(format!("FISHER_{a}_{b}"));
+        new_ids.push(format!("OR_{a}_{b}"));
+    }
+    let invalid: Vec<&str> = new_ids
+        .iter()
+        .filter(|id| !valid_info_id(id))
+        .map(|id| id.as_str())
+        .collect();
+    if !invalid.is_empty() {
+        anyhow::bail!("not valid VCF INFO ids: {}", invalid.join(", "));
+    }
+    let mut seen = HashSet::new();
+    if let Some(dup) = new_ids.iter().find(|id| !seen.insert(*id)) {
+        anyhow::bail!("INFO id {dup} would be written twice; check --name-template");
+    }
+    let collisions: Vec<&str> = new_ids
+        .iter()
+        .filter(|id| input_ids.contains(*id))
+        .map(|id| id.as_str())
+        .collect();
+    // --overwrite-existing clears them from every record first, so overwriting is intended
+    let overwrite = opts.force || opts.overwrite_existing;
+    // the bare --include-total ids (AF, AC, AN, ...) are in nearly every VCF, and asking for
+    // them is asking to recompute them
+    let total_ids: Vec<&String> = stat_suffixes
+        .iter()
+        .zip(&stat_ids)
+        .filter(|(suffix, _)| suffix.is_none())
+        .flat_map(|(_, ids)| ids)
+        .collect();
+    let (replaced_totals, blocking): (Vec<&str>, Vec<&str>) = collisions
+        .iter()
+        .partition(|id| total_ids.iter().any(|t| t == *id));
+    if !blocking.is_empty() && !overwrite && !opts.skip_existing && !opts.check && !opts.dry_run {
+        anyhow::bail!(
+            "{} INFO id(s) to be added are already defined in {}: {}; use --force to overwrite them or --skip-existing to keep them",
+            blocking.len(),
+            opts.input,
+            blocking.join(", ")
+        );
+    }
+    if !replaced_totals.is_empty() && !overwrite && !opts.skip_existing && !opts.dry_run {
+        warn!(
+            "--include-total replaces the values of {}, already defined in {}; use --skip-existing to keep them",
+            replaced_totals.join(", "),
+            opts.input
+        );
+    }
+    // --skip-existing: ids whose input values are left alone
+    let skipped: HashSet<String> = if opts.skip_existing && !overwrite {
+        collisions.iter().map(|id| id.to_string()).collect()
+    } else {
+        HashSet::new()
     };
 
     for grp in &groups {
         let count = group_map[grp].len();
-        for t in &want_tags {
-            match *t {
-                "AC" | "MAC" => add_info_line(
-                    &format!("{t}_{grp}"),
-                    "A",
-                    if *t == "AC" || *t == "MAC" {
-                        "Integer"
-                    } else {
-                        "Float"
-                    },
-                    &format!("{t} on {count} {grp} samples"),
-                ),
-                _ => add_info_line(
-                    &format!("{t}_{grp}"),
-                    "1",
-                    if ["AF", "MAF"].contains(t) {
-                        "Float"
-                    } else {
-                        "Integer"
-                    },
-                    &format!("{t} on {count} {grp} samples"),
-                ),
-            }
-        }
+        add_info_lines(
+            &mut out_hdr,
+            Some(grp),
+            &format!("{count} {grp}"),
+            tags,
+            &opts,
+            &input_ids,
+        );
+    }
+    if opts.include_total {
+        add_info_lines(
+            &mut out_hdr,
+            None,
+            &format!("all {n_counted}"),
+            tags,
+            &opts,
+            &input_ids,
+        );
+    }
+    if opts.with_total {
+        add_info_lines(
+            &mut out_hdr,
+            Some(&opts.total_label),
+            &format!("all {n_counted}"),
+            tags,
+            &opts,
+            &input_ids,
+        );
+    }
+    if opts.annotate_nalt {
+        push_info_line(
+            &mut out_hdr,
+            &input_ids,
+            "N_ALT",
+            "1",
+            "Integer",
+            "Number of ALT alleles of the record",
+        );
+    }
+    for (a, b) in &fst_pairs {
+        push_info_line(
+            &mut out_hdr,
+            &input_ids,
+            &format!("FST_{a}_{b}"),
+            "1",
+            "Float",
+            &format!("Weir & Cockerham Fst between {a} and {b}"),
+        );
+    }
+    for (a, b) in &assoc_pairs {
+        push_info_line(
+            &mut out_hdr,
+            &input_ids,
+            &format!("CHISQ_{a}_{b}"),
+            "1",
+            "Float",
+            &format!("Allelic chi-square statistic between {a} and {b}"),
+        );
+        push_info_line(
+            &mut out_hdr,
+            &input_ids,
+            &format!("P_{a}_{b}"),
+            "1",
+            "Float",
+            &format!(
+                "Allelic association p-value between {a} and {b}, Fisher's exact test when an expected count is below 5"
+            ),
+        );
+    }
+    for (a, b) in &compare_pairs {
+        push_info_line(
+            &mut out_hdr,
+            &input_ids,
+            &format!("FISHER_{a}_{b}"),
+            "A",
+            "Float",
+            &format!("Fisher's exact test p-value of the allele counts between {a} and {b}"),
+        );
+        push_info_line(
+            &mut out_hdr,
+            &input_ids,
+            &format!("OR_{a}_{b}"),
+            "A",
+            "Float",
+            &format!("Allele odds ratio of {a} over {b}"),
+        );
     }
+    // record how the annotations were made, like bcftools' ##bcftools_viewCommand
+    out_hdr.push_record(format!("##vcfgrpafVersion={}", env!("CARGO_PKG_VERSION")).as_bytes());
+    out_hdr.push_record(
+        format!(
+            "##vcfgrpafCommand={}; Labels={}; Groups={}; Date={}",
+            std::env::args().collect::<Vec<_>>().join(" "),
+            opts.labels,
+            groups.len(),
+            utc_timestamp()
+        )
+        .as_bytes(),
+    );
 
+    // with another --name-template, ids of the same shape as ours, e.g. `*_AF`
+    let template_match = |id: &str| {
+        opts.name_template != DEFAULT_NAME_TEMPLATE
+            && WANT_TAGS.iter().chain(&OPTIONAL_TAGS).any(|tag| {
+                let name = opts
+                    .name_template
+                    .replace("{stat}", opts.naming.tag_name(tag));
+                let (pre, post) = name.split_once("{group}").unwrap_or((&name, ""));
+                id.len() > pre.len() + post.len() && id.starts_with(pre) && id.ends_with(post)
+            })
+    };
     // generates all tags that start with all_tags in to a vec
     let mut all_tags_combination: Vec<String> = Vec::new();
     for (_, values) in headerview
@@ -205,98 +2690,579 @@ fn main() -> Result<()> {
     {
         let empty = String::from("NOT_FOUND");
         let id = values.get("ID").unwrap_or(&empty);
-        if all_tags.iter().any(|x| {
-            id.starts_with(x)
-        }) {
+        if all_tags.iter().any(|x| id.starts_with(x)) || id == "N_ALT" || template_match(id) {
             all_tags_combination.push(id.to_string());
         }
     }
 
-    eprintln!("Found related tags in input VCF:  {:?}", all_tags_combination);
+    if !opts.dry_run {
+        info!(
+            "Found related tags in input VCF:  {:?} ({})",
+            all_tags_combination,
+            if opts.overwrite_existing {
+                "removing"
+            } else {
+                "keeping, use --overwrite-existing to remove"
+            }
+        );
+    }
+
+    if opts.dry_run {
+        // a closed pipe (e.g. `| head`) ends the report, it is not an error
+        let report = || -> std::io::Result<()> {
+            let mut out = std::io::stdout().lock();
+            writeln!(out, "Groups:")?;
+            for grp in &groups {
+                let n_in_vcf = masks[grp].iter().filter(|&&m| m).count();
+                writeln!(out, "  {grp}: {n_in_vcf} samples")?;
+            }
+            writeln!(out, "INFO lines to add:")?;
+            // SAFETY: the duplicate is owned, and freed, by the view
+            let view = bcf::header::HeaderView::new(unsafe {
+                rust_htslib::htslib::bcf_hdr_dup(out_hdr.inner)
+            });
+            for record in view.header_records() {
+                let HeaderRecord::Info { values, .. } = record else {
+                    continue;
+                };
+                let Some(id) = values.get("ID") else {
+                    continue;
+                };
+                if input_ids.contains(id) || !new_ids.contains(id) {
+                    continue;
+                }
+                let fields: Vec<String> = values
+                    .iter()
+                    .filter(|(k, _)| k.as_str() != "IDX")
+                    .map(|(k, v)| format!("{k}={v}"))
+                    .collect();
+                writeln!(out, "  ##INFO=<{}>", fields.join(","))?;
+            }
+            if !collisions.is_empty() {
+                writeln!(
+                    out,
+                    "INFO ids already defined in {} ({}): {}",
+                    opts.input,
+                    if !skipped.is_empty() {
+                        "kept"
+                    } else if overwrite || blocking.is_empty() {
+                        "overwritten"
+                    } else {
+                        "needs --force or --skip-existing"
+                    },
+                    collisions.join(", ")
+                )?;
+            }
+            writeln!(
+                out,
+                "Related INFO ids in {} ({}): {}",
+                opts.input,
+                if opts.overwrite_existing {
+                    "removed"
+                } else {
+                    "kept"
+                },
+                if all_tags_combination.is_empty() {
+                    "none".to_string()
+                } else {
+                    all_tags_combination.join(", ")
+                }
+            )?;
+            out.flush()
+        };
+        match report() {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+            result => result.context("writing the --dry-run report")?,
+        }
+        return Ok(());
+    }
+
+    if opts.check {
+        // the report is the point of --check, so --quiet does not hide it
+        eprintln!("Groups:");
+        for grp in &groups {
+            let n_in_vcf = masks[grp].iter().filter(|&&m| m).count();
+            if opts.complement_group.as_ref() == Some(grp) {
+                eprintln!(
+                    "  {grp}: {n_in_vcf} samples (in no group of {})",
+                    opts.labels
+                );
+                continue;
+            }
+            eprintln!(
+                "  {grp}: {n_in_vcf} samples ({} in {})",
+                group_map[grp].len(),
+                opts.labels
+            );
+        }
+        if !blocking.is_empty() && !overwrite && !opts.skip_existing {
+            anyhow::bail!(
+                "{} INFO id(s) to be added are already defined in {}: {}",
+                blocking.len(),
+                opts.input,
+                blocking.join(", ")
+            );
+        }
+        if collisions.is_empty() {
+            eprintln!(
+                "Check passed: {} new INFO ids, none defined in {}",
+                new_ids.len(),
+                opts.input
+            );
+        } else {
+            eprintln!(
+                "Check passed: {} INFO ids to add, {} already defined in {} ({})",
+                new_ids.len(),
+                collisions.len(),
+                opts.input,
+                if skipped.is_empty() {
+                    "overwritten"
+                } else {
+                    "kept"
+                }
+            );
+        }
+        return Ok(());
+    }
 
     // open output file
-    let mut writer = if opts.output == "-" {
-        Writer::from_stdout(&out_hdr, true, bcf::Format::Vcf)?
-    } else {
-        Writer::from_path(&opts.output, &out_hdr, true, bcf::Format::Vcf)?
+    let guessed_type = OutputType::from_path(&opts.output);
+    let output_type = opts.output_type.unwrap_or(guessed_type);
+    let mismatch = match output_type {
+        OutputType::B | OutputType::U => !guessed_type.is_bcf(),
+        other => other != guessed_type,
+    };
+    if opts.format == OutputFormat::Vcf && opts.output != "-" && mismatch {
+        warn!(
+            "--output-type {} does not match the extension of {}",
+            format!("{output_type:?}").to_lowercase(),
+            opts.output
+        );
+    }
+    let (uncompressed, format) = output_type.writer_args();
+    if opts.write_index && (opts.format != OutputFormat::Vcf || opts.output == "-" || uncompressed)
+    {
+        anyhow::bail!("--write-index needs a bgzipped VCF or a BCF --output file");
+    }
+    let mut writer = None;
+    // --format tsv/jsonl
+    let mut text_out: Option<std::io::BufWriter<Box<dyn Write>>> = None;
+    match opts.format {
+        OutputFormat::Vcf if opts.output == "-" => {
+            writer = Some(Writer::from_stdout(&out_hdr, uncompressed, format)?)
+        }
+        OutputFormat::Vcf => {
+            writer = Some(Writer::from_path(
+                &opts.output,
+                &out_hdr,
+                uncompressed,
+                format,
+            )?)
+        }
+        OutputFormat::Tsv | OutputFormat::Jsonl => {
+            let out: Box<dyn Write> = if opts.output == "-" {
+                Box::new(std::io::stdout())
+            } else {
+                Box::new(
+                    std::fs::File::create(&opts.output)
+                        .with_context(|| format!("creating {}", opts.output))?,
+                )
+            };
+            let mut out = std::io::BufWriter::new(out);
+            if opts.format == OutputFormat::Tsv {
+                tsv_header(&mut out, &stat_suffixes, tags, &opts)?;
+            }
+            text_out = Some(out);
+        }
+    }
+
+    if let Some(writer) = writer.as_mut() {
+        if opts.io_threads > 1 {
+            writer
+                .set_threads(opts.io_threads)
+                .context("setting --io-threads on the output")?;
+        }
+    }
+
+    let mut stats_json = match &opts.stats_json {
+        Some(path) => Some(std::io::BufWriter::new(
+            std::fs::File::create(path).with_context(|| format!("creating {path}"))?,
+        )),
+        None => None,
     };
 
-    // process each record
-    let mut processed = 0;
-    for rec_result in bcf.records() {
-        processed += 1;
-        if processed % 10000 == 0 {
-            println!("Processed {processed} variants");
+    // created now so a bad path fails before the run rather than after it
+    if let Some(path) = &opts.summary_out {
+        std::fs::File::create(path).with_context(|| format!("creating {path}"))?;
+    }
+    let mut summary: Vec<(&str, usize, GroupSummary)> = groups
+        .iter()
+        .map(|grp| {
+            let n_in_vcf = masks[grp].iter().filter(|&&m| m).count();
+            (grp.as_str(), n_in_vcf, GroupSummary::default())
+        })
+        .collect();
+    if writes_total {
+        summary.push((&opts.total_label, n_counted, GroupSummary::default()));
+    }
+
+    let annotator = Annotator::new(opts.half_missing_policy.into()).config(config.clone());
+    // process records in batches: read on this thread, compute the statistics
+    // of a whole batch across the pool, then write them back in input order
+    let mut processed: u64 = 0;
+    let started = std::time::Instant::now();
+    let mut annotated = 0;
+    let mut failed = 0;
+    let mut n_below_min_ac = 0;
+    let mut n_errors = 0;
+    let mut batch: Vec<bcf::Record> = Vec::new();
+    let mut targeted: Vec<bool> = Vec::new();
+    let mut sex_regions: Vec<SexRegion> = Vec::new();
+    let mut eof = false;
+    while !eof {
+        targeted.clear();
+        sex_regions.clear();
+        while targeted.len() < BATCH_SIZE {
+            let n = targeted.len();
+            if n == batch.len() {
+                // records are reused from one batch to the next
+                batch.push(bcf.empty_record());
+            }
+            let rec = &mut batch[n];
+            let Some(rec_result) = bcf.read(rec) else {
+                eof = true;
+                break;
+            };
+            processed += 1;
+            // a read error is fatal even with --on-error skip: htslib gives the same error
+            // again on every later read of a truncated or corrupt file
+            let contig = rec_result
+                .and_then(|()| record_contig(rec, &headerview))
+                .with_context(|| format!("reading record #{processed}"))?;
+            if opts.progress_every > 0 && processed.is_multiple_of(opts.progress_every) {
+                let elapsed = started.elapsed().as_secs_f64();
+                let rate = processed as f64 / elapsed.max(1e-3);
+                let pos = rec.pos() + 1;
+                match n_records {
+                    // the index counts may be stale, so neither runs past the end
+                    Some(total) => info!(
+                        "Processed {processed} of {total} variants ({:.1}%), at {contig}:{pos} ({rate:.0} variants/s, {elapsed:.0}s elapsed, ETA {:.0}s)",
+                        (100.0 * processed as f64 / total.max(1) as f64).min(100.0),
+                        total.saturating_sub(processed) as f64 / rate,
+                    ),
+                    None => info!(
+                        "Processed {processed} variants, at {contig}:{pos} ({rate:.0} variants/s, {elapsed:.0}s elapsed)"
+                    ),
+                }
+            }
+            let start = rec.pos() as u64;
+            let end = (rec.end() as u64).saturating_sub(1).max(start);
+            let is_targeted = targets
+                .as_ref()
+                .is_none_or(|targets| targets.overlaps(&contig, start, end))
+                && (opts.chroms.is_empty() || opts.chroms.contains(&contig));
+            if !is_targeted && opts.drop_untargeted {
+                continue;
+            }
+            let is_allowed = (opts.apply_filters.is_empty()
+                || filter_allowed(rec, &headerview, &opts.apply_filters))
+                && !opts.exclude.iter().any(|e| e.matches(rec));
+            if !is_allowed && opts.filter_mode == FilterMode::Drop {
+                continue;
+            }
+            targeted.push(is_targeted && is_allowed && !sites_only);
+            sex_regions.push(
+                sex.as_ref()
+                    .map_or(SexRegion::Diploid, |sex| sex.region(&contig, start, end)),
+            );
         }
-        let mut rec = rec_result?;
+        let n = targeted.len();
 
-        // remove all_tags if present
+        // collect() keeps the input order, so no reordering is needed afterwards
+        let batch_stats = batch[..n]
+            .par_iter()
+            .zip(&targeted)
+            .zip(&sex_regions)
+            .map(|((rec, &is_targeted), &region)| {
+                is_targeted
+                    .then(|| {
+                        record_stats(
+                            rec,
+                            &annotator,
+                            &groups,
+                            &masks,
+                            total_mask.as_ref(),
+                            sex.as_ref().map(|sex| (sex, region)),
+                            &opts,
+                        )
+                    })
+                    .transpose()
+            })
+            .collect::<Vec<Result<_>>>();
 
-        let gt_vec_map: HashMap<String, Vec<Option<[Option<u8>; 2]>>> = {
-            let gts = rec.genotypes()?;
-            let mut map = HashMap::new();
-            for grp in &groups {
-                let mask = &masks[grp];
-                let mut gt_vec: Vec<Option<[Option<u8>; 2]>> = Vec::new();
-                for samp_idx in 0..headerview.samples().len() {
-                    let alleles = gts.get(samp_idx);
-                    if !mask[samp_idx] {
-                        continue;
+        for (rec, stats) in batch[..n].iter_mut().zip(batch_stats) {
+            let stats = match stats {
+                Ok(stats) => stats,
+                Err(e) => {
+                    let locus = format!("{}:{}", record_contig(rec, &headerview)?, rec.pos() + 1);
+                    if opts.on_error == OnError::Fail {
+                        return Err(e.context(format!("at {locus}")));
                     }
-                    // extract (max 2) alleles
-                    let mut pair = [None, None];
-                    for (i, a) in alleles.iter().take(2).enumerate() {
-                        pair[i] = match a.index() {
-                            Some(idx) if idx >= 0 => Some(idx as u8),
-                            _ => None,
-                        };
+                    // written unannotated, like a record outside --targets
+                    warn!("not annotating {locus}: {e:#}");
+                    n_errors += 1;
+                    None
+                }
+            };
+            // pooled over the whole (--samples) cohort, not per group
+            let below_min_ac = opts.min_ac.is_some_and(|min_ac| {
+                stats
+                    .as_ref()
+                    .and_then(|st| st.total.as_ref())
+                    .is_some_and(|total| total.ac.iter().skip(1).sum::<u32>() < min_ac)
+            });
+            if below_min_ac {
+                n_below_min_ac += 1;
+                if opts.drop_below_min_ac {
+                    continue;
+                }
+            }
+            let Some(stats) = stats.as_ref().filter(|_| !below_min_ac) else {
+                // outside --targets, rejected by --apply-filters or below --min-ac:
+                // passed through unchanged
+                if let Some(writer) = writer.as_mut() {
+                    for id in &stripped {
+                        clear_info(rec, id.as_bytes())?;
                     }
-                    if pair.iter().all(|x| x.is_none()) {
-                        gt_vec.push(None);
-                    } else {
-                        gt_vec.push(Some(pair));
+                    writer.translate(rec);
+                    writer.subset(rec);
+                    writer.write(rec)?;
+                }
+                continue;
+            };
+            let chrom = String::from_utf8_lossy(
+                headerview.rid2name(rec.rid().context("record has no contig")?)?,
+            )
+            .into_owned();
+            // same order as `stat_suffixes`
+            let mut suffixed: Vec<(Option<&str>, &AfStats)> = groups
+                .iter()
+                .map(|grp| (Some(grp.as_str()), &stats.groups[grp]))
+                .collect();
+            if let Some(total) = &stats.total {
+                if opts.include_total {
+                    suffixed.push((None, total));
+                }
+                if opts.with_total {
+                    suffixed.push((Some(&opts.total_label), total));
+                }
+            }
+            if opts.fail_action == FailAction::Drop
+                && suffixed
+                    .iter()
+                    .any(|(_, st)| !st.passes(opts.min_an, opts.min_call_rate))
+            {
+                failed += 1;
+                continue;
+            }
+            annotated += 1;
+            if opts.summary_out.is_some() {
+                // groups first, then the total
+                for (i, (_, _, sum)) in summary.iter_mut().enumerate() {
+                    match groups.get(i) {
+                        Some(grp) => sum.add(&stats.groups[grp]),
+                        None => stats.total.iter().for_each(|total| sum.add(total)),
                     }
                 }
-                map.insert(grp.clone(), gt_vec);
             }
-            map
-        };
 
-        for tag in all_tags_combination.iter() {
-            let full = format!("{tag}");
-            rec.push_info_string(&full.as_bytes(), &[])?;
-        }
-
-        for grp in &groups {
-            let stats = calc_af(&gt_vec_map[grp]);
-
-            for tag in &want_tags {
-                let full = format!("{tag}_{grp}");
-
-                rec.push_info_string(&full.as_bytes(), &[])?;
+            if let Some(json) = stats_json.as_mut() {
+                let alleles: Vec<String> = rec
+                    .alleles()
+                    .iter()
+                    .map(|a| String::from_utf8_lossy(a).into_owned())
+                    .collect();
+                let mut group_stats: Vec<(&str, &AfStats)> = groups
+                    .iter()
+                    .map(|grp| (grp.as_str(), &stats.groups[grp]))
+                    .collect();
+                if let Some(total) = stats.total.as_ref().filter(|_| writes_total) {
+                    group_stats.push((&opts.total_label, total));
+                }
+                let site = SiteStats {
+                    chrom: &chrom,
+                    pos: rec.pos() + 1,
+                    ref_allele: &alleles[0],
+                    alt: alleles[1..].iter().map(|a| a.as_str()).collect(),
+                    groups: group_stats,
+                };
+                serde_json::to_writer(&mut *json, &site)?;
+                writeln!(json)?;
+            }
 
-                match *tag {
-                    "AC" => rec.push_info_integer(&full.as_bytes(), &[stats.ac[1] as i32])?,
-                    "MAC" => rec.push_info_integer(&full.as_bytes(), &[stats.mac as i32])?,
-                    "AN" => rec.push_info_integer(&full.as_bytes(), &[stats.an as i32])?,
-                    "N_HEMI" => rec.push_info_integer(&full.as_bytes(), &[stats.n_hemi as i32])?,
-                    "N_MISS" => rec.push_info_integer(&full.as_bytes(), &[stats.n_miss as i32])?,
-                    "N_HOMREF" => {
-                        rec.push_info_integer(&full.as_bytes(), &[stats.n_homref as i32])?
+            if let Some(out) = text_out.as_mut() {
+                if opts.format == OutputFormat::Tsv {
+                    write_tsv_rows(out, &chrom, rec, &suffixed, tags, &opts)?;
+                } else {
+                    let id = String::from_utf8_lossy(&rec.id()).into_owned();
+                    let alleles: Vec<String> = rec
+                        .alleles()
+                        .iter()
+                        .map(|a| String::from_utf8_lossy(a).into_owned())
+                        .collect();
+                    let mut group_tags: Vec<(&str, GroupTags)> = groups
+                        .iter()
+                        .map(|grp| {
+                            let stats = &stats.groups[grp];
+                            (
+                                grp.as_str(),
+                                GroupTags {
+                                    stats,
+                                    tags,
+                                    opts: &opts,
+                                },
+                            )
+                        })
+                        .collect();
+                    if let Some(total) = stats.total.as_ref().filter(|_| writes_total) {
+                        group_tags.push((
+                            &opts.total_label,
+                            GroupTags {
+                                stats: total,
+                                tags,
+                                opts: &opts,
+                            },
+                        ));
                     }
-                    "N_HET" => rec.push_info_integer(&full.as_bytes(), &[stats.n_het as i32])?,
-                    "N_HOMALT" => {
-                        rec.push_info_integer(&full.as_bytes(), &[stats.n_homalt as i32])?
+                    let line = JsonlRecord {
+                        chrom: &chrom,
+                        pos: rec.pos() + 1,
+                        id: &id,
+                        ref_allele: &alleles[0],
+                        alt: alleles[1..].iter().map(|a| a.as_str()).collect(),
+                        groups: group_tags,
+                    };
+                    serde_json::to_writer(&mut *out, &line)?;
+                    writeln!(out)?;
+                }
+            }
+
+            if let Some(writer) = writer.as_mut() {
+                // cleared while the record still has the input header, which defines them
+                for id in &stripped {
+                    clear_info(rec, id.as_bytes())?;
+                }
+                // switch the record over to the output header, which has the new INFO ids
+                writer.translate(rec);
+                // tags of groups being recomputed are replaced either way
+                if opts.overwrite_existing {
+                    for tag in all_tags_combination.iter() {
+                        clear_info(rec, tag.as_bytes())?;
                     }
-                    "AF" => rec.push_info_float(&full.as_bytes(), &[stats.af as f32])?,
-                    "MAF" => rec.push_info_float(&full.as_bytes(), &[stats.maf as f32])?,
-                    _ => {}
                 }
+                for ((_, st), ids) in suffixed.iter().zip(&stat_ids) {
+                    push_stats(rec, ids, st, tags, &opts, &skipped)?;
+                }
+                let mut pairs: Vec<(String, Vec<f32>)> = Vec::new();
+                for (a, b) in &fst_pairs {
+                    let fst = calc_fst([&stats.groups[a], &stats.groups[b]]);
+                    pairs.push((
+                        format!("FST_{a}_{b}"),
+                        vec![fst.map_or(f32::missing(), |f| f as f32)],
+                    ));
+                }
+                for (a, b) in &assoc_pairs {
+                    let assoc = calc_assoc([&stats.groups[a], &stats.groups[b]]);
+                    let (chisq, p) = assoc
+                        .map_or((f32::missing(), f32::missing()), |(chisq, p)| {
+                            (chisq as f32, p as f32)
+                        });
+                    pairs.push((format!("CHISQ_{a}_{b}"), vec![chisq]));
+                    pairs.push((format!("P_{a}_{b}"), vec![p]));
+                }
+                for (a, b) in &compare_pairs {
+                    let (p, or): (Vec<f32>, Vec<f32>) =
+                        calc_compare([&stats.groups[a], &stats.groups[b]])
+                            .into_iter()
+                            .map(|(p, or)| {
+                                (
+                                    p.map_or(f32::missing(), |p| p as f32),
+                                    or.map_or(f32::missing(), |or| or as f32),
+                                )
+                            })
+                            .unzip();
+                    pairs.push((format!("FISHER_{a}_{b}"), p));
+                    pairs.push((format!("OR_{a}_{b}"), or));
+                }
+                if opts.annotate_nalt && !skipped.contains("N_ALT") {
+                    let n_alt = rec.allele_count().saturating_sub(1) as i32;
+                    rec.push_info_integer(b"N_ALT", &[n_alt])?;
+                }
+                for (id, values) in pairs.iter().filter(|(id, _)| !skipped.contains(id)) {
+                    rec.push_info_float(id.as_bytes(), values)?;
+                }
+                // genotypes go only after the statistics were computed from them
+                writer.subset(rec);
+                writer.write(rec)?;
             }
         }
-        writer.write(&rec)?;
+        // keep a reader on the other end of a pipe fed
+        if let Some(json) = stats_json.as_mut() {
+            json.flush()?;
+        }
+    }
+
+    if let Some(out) = text_out.as_mut() {
+        out.flush()?;
+    }
+    if let Some(path) = &opts.summary_out {
+        write_summary(path, &summary)?;
+    }
+    if opts.write_index {
+        // the index can only be built over a closed file
+        drop(writer);
+        build_index(&opts.output, output_type.is_bcf(), opts.io_threads)?;
     }
 
-    println!("Finished vcfgrpaf");
+    let elapsed = started.elapsed().as_secs_f64();
+    info!("Summary:");
+    info!(
+        "  variants read:      {processed} in {elapsed:.1}s ({:.0} variants/s)",
+        processed as f64 / elapsed.max(1e-3)
+    );
+    info!("  variants annotated: {annotated}");
+    if opts.fail_action == FailAction::Drop {
+        info!("  variants dropped:   {failed} (--min-an/--min-call-rate)");
+    }
+    if opts.on_error == OnError::Skip {
+        info!("  bad records:        {n_errors} (not annotated)");
+    }
+    if opts.min_ac.is_some() {
+        info!(
+            "  below --min-ac:     {n_below_min_ac} ({})",
+            if opts.drop_below_min_ac {
+                "dropped"
+            } else {
+                "not annotated"
+            }
+        );
+    }
+    info!("  groups:             {}", groups.len());
+    for grp in &groups {
+        let n_in_vcf = masks[grp].iter().filter(|&&m| m).count();
+        if opts.complement_group.as_ref() == Some(grp) {
+            info!(
+                "    {grp}: {n_in_vcf} samples (in no group of {})",
+                opts.labels
+            );
+            continue;
+        }
+        info!(
+            "    {grp}: {n_in_vcf} samples ({} in {})",
+            group_map[grp].len(),
+            opts.labels
+        );
+    }
+    info!("Finished vcfgrpaf");
     Ok(())
 }
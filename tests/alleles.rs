@@ -0,0 +1,65 @@
+// tests/alleles.rs
+//! Allele indices past 255 must not wrap around, and ones past the site's alleles are
+//! missing
+mod common;
+
+#[test]
+fn more_than_255_alleles() {
+    let n_alt = 300;
+    let alts: Vec<String> = (0..n_alt)
+        .map(|i| format!("A{}", "C".repeat(i + 1)))
+        .collect();
+    let rec = format!(
+        "chr1\t100\t.\tA\t{}\t.\tPASS\t.\tGT\t0/300\t256/300",
+        alts.join(",")
+    );
+    let out = common::annotate(
+        &common::vcf(2, &[&rec]),
+        "S1\tA\nS2\tA\n",
+        &["--tags", "AC,AN"],
+    );
+    let info = common::info_fields(common::info_columns(&out)[0]);
+    let ac: Vec<u32> = info["AC_A"]
+        .split(',')
+        .map(|x| x.parse().unwrap())
+        .collect();
+    assert_eq!(ac.len(), n_alt);
+    // as u8, alleles 256 and 300 would wrap to REF and ALT 44
+    let mut expected = vec![0; n_alt];
+    expected[255] = 1;
+    expected[299] = 2;
+    assert_eq!(ac, expected);
+    assert_eq!(info["AN_A"], "4");
+}
+
+#[test]
+fn allele_past_the_alts() {
+    // 0/2 at a site with one ALT
+    let vcf = common::vcf(
+        2,
+        &[
+            "chr1\t100\t.\tA\tC\t.\tPASS\t.\tGT\t0/1\t0/2",
+            "chr1\t200\t.\tA\tC\t.\tPASS\t.\tGT\t0/1\t0/0",
+        ],
+    );
+    let tags = ["--tags", "AC,AN,N_PARTIAL,N_HET"];
+    let out = common::annotate(&vcf, "S1\tA\nS2\tA\n", &tags);
+    // 0/2 is read as 0/., as the library does below
+    assert_eq!(
+        common::info_columns(&out),
+        [
+            "AC_A=1;AN_A=3;N_PARTIAL_A=1;N_HET_A=1",
+            "AC_A=1;AN_A=4;N_PARTIAL_A=0;N_HET_A=1"
+        ]
+    );
+}
+
+#[test]
+fn library_counts_bad_alleles_as_missing() {
+    use vcfgrpaf::{calc_af, Gt, HalfMissing};
+    let gts: Vec<Gt> = vec![vec![Some(0), Some(5)], vec![Some(1), Some(1)]];
+    let st = calc_af(&gts.iter().collect::<Vec<_>>(), 2, HalfMissing::Partial);
+    // 0/5 is read as 0/.
+    assert_eq!((st.ac, st.an), (vec![1, 2], 3));
+    assert_eq!((st.n_partial, st.n_homalt), (1, 1));
+}
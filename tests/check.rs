@@ -0,0 +1,35 @@
+// tests/check.rs
+//! --check reports on stderr even with --quiet, whether it passes or fails
+mod common;
+
+fn check(header: &str) -> (bool, String) {
+    let vcf = common::vcf(2, &["chr1\t100\t.\tA\tC\t.\tPASS\t.\tGT\t0/1\t0/0"]).replacen(
+        "##FORMAT",
+        &format!("{header}##FORMAT"),
+        1,
+    );
+    let input = common::temp_file("check.vcf", &vcf);
+    let labels = common::temp_file("check_groups", "S1\tA\nS2\tA\n");
+    let out = common::run(&input, &labels, &["--check", "--tags", "AC,AN"]);
+    std::fs::remove_file(&input).ok();
+    std::fs::remove_file(&labels).ok();
+    assert!(out.stdout.is_empty());
+    (out.status.success(), String::from_utf8(out.stderr).unwrap())
+}
+
+#[test]
+fn quiet_check_passes_visibly() {
+    let (passed, stderr) = check("");
+    assert!(passed, "{stderr}");
+    assert!(stderr.contains("  A: 2 samples"), "{stderr}");
+    assert!(stderr.contains("Check passed: 2 new INFO ids"), "{stderr}");
+}
+
+#[test]
+fn quiet_check_fails_visibly() {
+    let (passed, stderr) =
+        check("##INFO=<ID=AN_A,Number=1,Type=Integer,Description=\"Allele number\">\n");
+    assert!(!passed);
+    assert!(stderr.contains("already defined"), "{stderr}");
+    assert!(stderr.contains("AN_A"), "{stderr}");
+}
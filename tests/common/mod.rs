@@ -0,0 +1,99 @@
+// tests/common/mod.rs
+//! Running vcfgrpaf and reading its output, shared by the integration tests
+#![allow(dead_code)]
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A file under test/
+pub fn fixture(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test")
+        .join(path)
+}
+
+/// A path in the temp dir that no other test of this run uses
+pub fn temp_path(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("vcfgrpaf_{}_{n}_{name}", std::process::id()))
+}
+
+/// Write `contents` to a new temp file
+pub fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let path = temp_path(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// A VCF with a GT line, samples S1 to S<n_samples> and these tab-separated records on chr1
+pub fn vcf(n_samples: usize, records: &[&str]) -> String {
+    let samples: Vec<String> = (1..=n_samples).map(|i| format!("S{i}")).collect();
+    let mut vcf = format!(
+        "##fileformat=VCFv4.2\n##contig=<ID=chr1,length=1000>\n\
+         ##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n\
+         #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}\n",
+        samples.join("\t")
+    );
+    for rec in records {
+        vcf.push_str(rec);
+        vcf.push('\n');
+    }
+    vcf
+}
+
+/// vcfgrpaf on `input` with the `labels` file, quiet, and `args`
+pub fn run(input: &Path, labels: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_vcfgrpaf"))
+        .arg(input)
+        .arg("-l")
+        .arg(labels)
+        .arg("--quiet")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// The VCF vcfgrpaf writes to stdout for the `vcf` and `labels` texts
+pub fn annotate(vcf: &str, labels: &str, args: &[&str]) -> String {
+    let input = temp_file("input.vcf", vcf);
+    let labels = temp_file("groups", labels);
+    let out = run(&input, &labels, &[&["-o", "-"], args].concat());
+    std::fs::remove_file(&input).ok();
+    std::fs::remove_file(&labels).ok();
+    assert!(
+        out.status.success(),
+        "vcfgrpaf failed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+/// The VCF vcfgrpaf writes to stdout for test/<name>.vcf and its test/<name>_groups labels
+pub fn annotate_fixture(name: &str, args: &[&str]) -> String {
+    let input = fixture(&format!("{name}.vcf"));
+    let labels = fixture(&format!("{name}_groups"));
+    let out = run(&input, &labels, &[&["-o", "-"], args].concat());
+    assert!(
+        out.status.success(),
+        "vcfgrpaf failed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+/// INFO column of every record
+pub fn info_columns(vcf: &str) -> Vec<&str> {
+    vcf.lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split('\t').nth(7).unwrap())
+        .collect()
+}
+
+/// `key=value` pairs of an INFO column; flags are left out
+pub fn info_fields(info: &str) -> HashMap<&str, &str> {
+    info.split(';')
+        .filter_map(|kv| kv.split_once('='))
+        .collect()
+}
@@ -0,0 +1,25 @@
+// tests/complement.rs
+//! --complement-group names must not clash with the other group suffixes
+mod common;
+
+fn run(args: &[&str]) -> std::process::Output {
+    let args = [&["-o", "-", "--tags", "AC,AN"], args].concat();
+    common::run(
+        &common::fixture("truvari.vcf"),
+        &common::fixture("chroms_groups"),
+        &args,
+    )
+}
+
+#[test]
+fn complement_named_like_the_total() {
+    let out = run(&["--complement-group", "ALL", "--with-total"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.contains("--complement-group and --total-label are both ALL"),
+        "{stderr}"
+    );
+    // without --with-total there is no ALL group to clash with
+    assert!(run(&["--complement-group", "ALL"]).status.success());
+}
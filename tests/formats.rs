@@ -0,0 +1,23 @@
+// tests/formats.rs
+//! --format tsv writes the same values as the VCF INFO fields
+mod common;
+
+#[test]
+fn tsv_floats_match_the_vcf() {
+    let tags = ["--tags", "AF,MAF,AN"];
+    let vcf = common::annotate_fixture("triallelic", &tags);
+    let info = common::info_fields(common::info_columns(&vcf)[0]);
+    let tsv = common::annotate_fixture("triallelic", &[&tags[..], &["--format", "tsv"]].concat());
+    let mut rows = tsv.lines().map(|line| line.split('\t').collect::<Vec<_>>());
+    let header = rows.next().unwrap();
+    let rows: Vec<Vec<&str>> = rows.collect();
+    // one row per ALT
+    assert_eq!(rows.len(), 2);
+    for id in ["AF_A", "MAF_A", "AF_B", "MAF_B"] {
+        let col = header.iter().position(|c| *c == id).unwrap();
+        let tsv_values: Vec<&str> = rows.iter().map(|row| row[col]).collect();
+        assert_eq!(tsv_values.join(","), info[id], "{id}");
+    }
+    // 1/3, not the f32 noise 0.33333334
+    assert_eq!(info["AF_A"], "0.333333,0.333333");
+}
@@ -0,0 +1,43 @@
+// tests/half_missing.rs
+//! --half-missing-policy on test/half_missing.vcf: `./1`, `1/.`, `./.` and `0/1`, all in
+//! group A
+mod common;
+
+const TAGS: &str = "AC,AN,N_HEMI,N_PARTIAL,N_MISS,N_CALLED,N_HOMREF,N_HET,N_HOMALT";
+
+fn counts(extra: &[&str]) -> Vec<String> {
+    let out = common::annotate_fixture("half_missing", &[&["--tags", TAGS], extra].concat());
+    let info = common::info_fields(common::info_columns(&out)[0]);
+    TAGS.split(',')
+        .map(|tag| info[format!("{tag}_A").as_str()].to_string())
+        .collect()
+}
+
+#[test]
+fn partial_by_default() {
+    // the called alleles of ./1 and 1/. count, the samples in N_PARTIAL
+    assert_eq!(counts(&[]), ["3", "4", "0", "2", "1", "3", "0", "1", "0"]);
+    // as versions before N_PARTIAL did
+    assert_eq!(
+        counts(&["--half-missing-policy", "hemi"]),
+        ["3", "4", "2", "0", "1", "3", "0", "1", "0"]
+    );
+}
+
+#[test]
+fn drop_counts_the_call_as_missing() {
+    // only 0/1 is left: AC 1, AN 2, and three samples in N_MISS
+    let expected = ["1", "2", "0", "0", "3", "1", "0", "1", "0"];
+    assert_eq!(counts(&["--half-missing-policy", "drop"]), expected);
+    // gnomAD's name for it
+    assert_eq!(counts(&["--half-call-mode", "missing"]), expected);
+}
+
+#[test]
+fn keep_classes_the_call_by_its_alleles() {
+    // ./1 and 1/. are homozygous ALT
+    assert_eq!(
+        counts(&["--half-missing-policy", "keep"]),
+        ["3", "4", "0", "0", "1", "3", "0", "1", "2"]
+    );
+}
@@ -0,0 +1,77 @@
+// tests/header_types.rs
+//! Every value written for a statistic must fit the Number and Type its header line
+//! declares
+mod common;
+use std::collections::HashMap;
+
+const ALL_TAGS: &str = "AF,MAF,MAC,AC,AN,N_HEMI,N_MISS,N_CALLED,N_HOMREF,N_HET,N_HOMALT,HWE,\
+                        ExcHet,F_MISSING,AC_Het,AC_Hom,SINGLETON,DOUBLETON,N_PARTIAL,\
+                        InbreedingCoeff,N_PHASED,RARE,HET_AB,N_SINGLETON,N_DOUBLETON,N_DIPLOID";
+
+fn check(fixture: &str, extra: &[&str]) {
+    let args = ["-o", "-", "--tags", ALL_TAGS, "--rare-threshold", "2"];
+    let out = common::run(
+        &common::fixture(&format!("{fixture}.vcf")),
+        &common::fixture(&format!("{fixture}_groups")),
+        &[&args, extra].concat(),
+    );
+    assert!(out.status.success());
+    let vcf = String::from_utf8(out.stdout).unwrap();
+
+    // ID -> (Number, Type) of the INFO header lines
+    let declared: HashMap<&str, (&str, &str)> = vcf
+        .lines()
+        .filter_map(|line| line.strip_prefix("##INFO=<"))
+        .map(|line| {
+            let field = |key: &str| line.split(',').find_map(|kv| kv.strip_prefix(key)).unwrap();
+            (field("ID="), (field("Number="), field("Type=")))
+        })
+        .collect();
+    for line in vcf.lines().filter(|line| !line.starts_with('#')) {
+        let cols: Vec<&str> = line.split('\t').collect();
+        let n_alt = cols[4].split(',').count();
+        for kv in cols[7].split(';') {
+            let (id, value) = kv.split_once('=').unwrap_or((kv, ""));
+            let (number, typ) = declared[id];
+            let site = format!("{fixture} {extra:?} {}:{} {id}", cols[0], cols[1]);
+            if typ == "Flag" {
+                assert_eq!((number, value), ("0", ""), "{site}");
+                continue;
+            }
+            let values: Vec<&str> = value.split(',').collect();
+            let expected = match number {
+                "A" => n_alt,
+                "R" => n_alt + 1,
+                n => n.parse().unwrap(),
+            };
+            // a vector that is missing as a whole may be written as a single `.`
+            assert!(
+                values.len() == expected || value == ".",
+                "{site}: {} values for Number={number}",
+                values.len()
+            );
+            for v in values.iter().filter(|v| **v != ".") {
+                match typ {
+                    "Integer" => assert!(v.parse::<i64>().is_ok(), "{site}: {v} is not an Integer"),
+                    "Float" => assert!(v.parse::<f64>().is_ok(), "{site}: {v} is not a Float"),
+                    _ => panic!("{site}: unexpected Type={typ}"),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn values_match_header() {
+    for fixture in [
+        "triallelic",
+        "truvari",
+        "het_ab",
+        "tetraploid",
+        "half_missing",
+    ] {
+        check(fixture, &[]);
+        check(fixture, &["--ac-number", "R"]);
+        check(fixture, &["--naming", "gnomad"]);
+    }
+}
@@ -0,0 +1,34 @@
+// tests/on_error.rs
+//! --on-error skip leaves records with bad genotypes unannotated, but a file that can't be
+//! read any further still stops the run
+mod common;
+
+#[test]
+fn truncated_bcf_is_fatal() {
+    // enough records for several BGZF blocks, so the header survives the truncation
+    let records: Vec<String> = (1..=2000)
+        .map(|pos| {
+            let gts = vec!["0/1"; 100].join("\t");
+            format!("chr1\t{pos}\t.\tA\tC\t.\tPASS\t.\tGT\t{gts}")
+        })
+        .collect();
+    let records: Vec<&str> = records.iter().map(String::as_str).collect();
+    let vcf = common::vcf(100, &records).replace("length=1000", "length=2000");
+    let input = common::temp_file("truncated.vcf", &vcf);
+    let labels = common::temp_file("truncated_groups", "S1\tA\nS2\tA\n");
+    let bcf = common::temp_path("truncated.bcf");
+    let args = ["-o", bcf.to_str().unwrap(), "--tags", "AN"];
+    assert!(common::run(&input, &labels, &args).status.success());
+    let full = std::fs::read(&bcf).unwrap();
+    std::fs::write(&bcf, &full[..full.len() / 2]).unwrap();
+
+    let args = ["-o", "-", "--tags", "AC", "--on-error", "skip"];
+    let out = common::run(&bcf, &labels, &args);
+    for path in [&input, &labels, &bcf] {
+        std::fs::remove_file(path).ok();
+    }
+    assert_eq!(out.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("reading record #"), "{stderr}");
+    assert!(!stderr.contains("skipping"), "{stderr}");
+}
@@ -0,0 +1,34 @@
+// tests/partial.rs
+//! Partially missing diploid calls are counted apart from haploid ones
+mod common;
+
+/// Output for one record with calls `0/.`, `./1`, `0` and `./.`, all in group A
+fn annotate(extra: &[&str]) -> String {
+    let vcf = common::vcf(4, &["chr1\t100\t.\tA\tC\t.\tPASS\t.\tGT\t0/.\t./1\t0\t./."]);
+    let tags = ["--tags", "AC,AN,N_HEMI,N_PARTIAL,N_MISS,N_CALLED"];
+    common::annotate(
+        &vcf,
+        "S1\tA\nS2\tA\nS3\tA\nS4\tA\n",
+        &[&tags, extra].concat(),
+    )
+}
+
+#[test]
+fn partial_is_not_hemizygous() {
+    let out = annotate(&[]);
+    let info = common::info_fields(common::info_columns(&out)[0]);
+    // the called alleles of 0/. and ./1 still count
+    assert_eq!((info["AC_A"], info["AN_A"]), ("1", "3"));
+    // only the haploid 0 is hemizygous
+    assert_eq!(info["N_HEMI_A"], "1");
+    assert_eq!(info["N_PARTIAL_A"], "2");
+    assert_eq!((info["N_MISS_A"], info["N_CALLED_A"]), ("1", "3"));
+}
+
+#[test]
+fn partial_as_hemi() {
+    let out = annotate(&["--half-missing-policy", "hemi"]);
+    let info = common::info_fields(common::info_columns(&out)[0]);
+    assert_eq!((info["N_HEMI_A"], info["N_PARTIAL_A"]), ("3", "0"));
+    assert_eq!(info["AN_A"], "3");
+}
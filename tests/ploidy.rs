@@ -0,0 +1,64 @@
+// tests/ploidy.rs
+//! Calls of any ploidy count every called allele toward AC/AN, and only the diploid ones
+//! toward HWE/ExcHet
+mod common;
+
+#[test]
+fn tetraploid_counts() {
+    let out = common::annotate_fixture(
+        "tetraploid",
+        &["--tags", "AC,AN,N_HEMI,N_PARTIAL,N_MISS,N_CALLED"],
+    );
+    let info = common::info_columns(&out);
+    // 0/0/1/1, 1/1/1/1 and 0/0/0/0 in A; 0/1/1/., ./././. and 0/0/0/1 in B
+    let first = common::info_fields(info[0]);
+    assert_eq!((first["AC_A"], first["AN_A"]), ("6", "12"));
+    assert_eq!((first["AC_B"], first["AN_B"]), ("3", "7"));
+    assert_eq!((first["N_MISS_B"], first["N_CALLED_B"]), ("1", "2"));
+    // 0/1/1/. is partially missing, not hemizygous
+    assert_eq!((first["N_HEMI_B"], first["N_PARTIAL_B"]), ("0", "1"));
+    let second = common::info_fields(info[1]);
+    assert_eq!((second["AC_A"], second["AN_A"]), ("2,6", "12"));
+    assert_eq!((second["AC_B"], second["AN_B"]), ("4,1", "7"));
+}
+
+#[test]
+fn mixed_ploidy_exact_test() {
+    let tags = ["--tags", "AC,AN,N_HEMI,HWE,ExcHet,N_DIPLOID"];
+    let out = common::annotate_fixture("mixed_ploidy", &tags);
+    let info = common::info_fields(common::info_columns(&out)[0]);
+    // the haploid 1 and 1 and the tetraploid 0/0/1/1 still count in AC/AN
+    assert_eq!(
+        (info["AC_A"], info["AN_A"], info["N_HEMI_A"]),
+        ("6", "12", "2")
+    );
+    // but the test only sees the diploid 0/1, 0/1 and 0/0
+    assert_eq!(info["N_DIPLOID_A"], "3");
+    assert_eq!((info["HWE_A"], info["ExcHet_A"]), ("1", "0.8"));
+}
+
+#[test]
+fn fst_only_from_diploid_calls() {
+    let fst = |gts: &[&str], labels: &str| {
+        let rec = format!("chr1\t100\t.\tA\tC\t.\tPASS\t.\tGT\t{}", gts.join("\t"));
+        let out = common::annotate(
+            &common::vcf(gts.len(), &[&rec]),
+            labels,
+            &["--tags", "AN", "--fst", "A,B"],
+        );
+        common::info_fields(common::info_columns(&out)[0])["FST_A_B"].to_string()
+    };
+    let diploid = fst(
+        &["0/1", "0/1", "0/0", "0/0", "0/0", "1/1"],
+        "S1\tA\nS2\tA\nS3\tA\nS4\tB\nS5\tB\nS6\tB\n",
+    );
+    assert_ne!(diploid, ".");
+    // a tetraploid and a haploid call in A, a tetraploid and a partial call in B
+    let mixed = fst(
+        &[
+            "0/1", "0/1", "0/0", "1/1/1/1", "1", "0/0", "0/0", "1/1", "0/0/0/0", "./1",
+        ],
+        "S1\tA\nS2\tA\nS3\tA\nS4\tA\nS5\tA\nS6\tB\nS7\tB\nS8\tB\nS9\tB\nS10\tB\n",
+    );
+    assert_eq!(mixed, diploid);
+}
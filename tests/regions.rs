@@ -0,0 +1,45 @@
+// tests/regions.rs
+//! --regions fetches each record once, and `chr:pos` is that one position
+mod common;
+
+/// POS of the records read from an indexed copy of a VCF with a 401 bp deletion at 100
+/// and a SNV at 500
+fn positions(regions: &str) -> Vec<String> {
+    let deletion = format!("chr1\t100\t.\tA{}\tA\t.\tPASS\t.\tGT\t0/1", "C".repeat(400));
+    let vcf = common::vcf(1, &[&deletion, "chr1\t500\t.\tA\tC\t.\tPASS\t.\tGT\t0/1"]);
+    let input = common::temp_file("regions.vcf", &vcf);
+    let labels = common::temp_file("regions_groups", "S1\tA\n");
+    let indexed = common::temp_path("regions.vcf.gz");
+    let indexed_str = indexed.to_str().unwrap();
+    let args = ["-o", indexed_str, "--tags", "AN", "--write-index"];
+    assert!(common::run(&input, &labels, &args).status.success());
+    let args = ["-o", "-", "--tags", "AC", "--regions", regions];
+    let out = common::run(&indexed, &labels, &args);
+    for path in [&input, &labels, &indexed] {
+        std::fs::remove_file(path).ok();
+    }
+    std::fs::remove_file(format!("{indexed_str}.tbi")).ok();
+    assert!(out.status.success());
+    String::from_utf8(out.stdout)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split('\t').nth(1).unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn record_in_two_regions_once() {
+    assert_eq!(positions("chr1:150-160,chr1:300-310"), ["100"]);
+    assert_eq!(
+        positions("chr1:150-160,chr1:300-310,chr1:500-"),
+        ["100", "500"]
+    );
+}
+
+#[test]
+fn single_position() {
+    assert_eq!(positions("chr1:120"), ["100"]);
+    assert_eq!(positions("chr1:501"), Vec::<String>::new());
+    assert_eq!(positions("chr1:120-"), ["100", "500"]);
+}
@@ -0,0 +1,132 @@
+// tests/roundtrip.rs
+//! Annotating a file annotated before: keeping, replacing or clearing the old fields
+mod common;
+use std::path::Path;
+
+fn vcfgrpaf(input: &Path, labels: &str, extra: &[&str]) -> std::process::Output {
+    let labels_path = common::temp_file("roundtrip_groups", labels);
+    let args = [&["-o", "-", "--tags", "AC,AN"], extra].concat();
+    let out = common::run(input, &labels_path, &args);
+    std::fs::remove_file(&labels_path).ok();
+    out
+}
+
+#[test]
+fn keep_old_tags() {
+    let input = common::fixture("triallelic.vcf");
+    // EUR is first annotated from S1 and S2 only
+    let first = vcfgrpaf(&input, "S1\tEUR\nS2\tEUR\n", &[]);
+    assert!(first.status.success());
+    let annotated = common::temp_path("roundtrip.vcf");
+    std::fs::write(&annotated, &first.stdout).unwrap();
+
+    let eur_afr = "S1\tEUR\nS2\tEUR\nS3\tEUR\nS4\tAFR\nS5\tAFR\nS6\tAFR\n";
+    // the same ids again need a flag
+    assert!(!vcfgrpaf(&annotated, eur_afr, &[]).status.success());
+    let kept = vcfgrpaf(&annotated, eur_afr, &["--keep-old-tags"]);
+    let forced = vcfgrpaf(&annotated, eur_afr, &["--force"]);
+    std::fs::remove_file(&annotated).ok();
+
+    let kept = String::from_utf8(kept.stdout).unwrap();
+    assert_eq!(
+        common::info_columns(&kept)[0],
+        "AC_EUR=1,1;AN_EUR=4;AC_AFR=0,2;AN_AFR=4"
+    );
+    // the header still declares each id once
+    assert_eq!(kept.matches("##INFO=<ID=AC_EUR,").count(), 1);
+    let forced = String::from_utf8(forced.stdout).unwrap();
+    assert_eq!(
+        common::info_columns(&forced)[0],
+        "AC_EUR=2,2;AN_EUR=6;AC_AFR=0,2;AN_AFR=4"
+    );
+}
+
+#[test]
+fn overwrite_association_run() {
+    let labels = common::fixture("truvari_groups");
+    let first = common::temp_path("assoc.vcf");
+    let args = [
+        "-o",
+        first.to_str().unwrap(),
+        "--tags",
+        "AC",
+        "--assoc",
+        "A,B",
+        "--compare",
+        "A:B",
+        "--annotate-nalt",
+    ];
+    let out = common::run(&common::fixture("truvari.vcf"), &labels, &args);
+    assert!(out.status.success());
+    let args = ["-o", "-", "--tags", "AC", "--overwrite-existing"];
+    let second = common::run(&first, &labels, &args);
+    std::fs::remove_file(&first).ok();
+    assert!(second.status.success());
+
+    let vcf = String::from_utf8(second.stdout).unwrap();
+    for info in common::info_columns(&vcf) {
+        // CHISQ, P, FISHER, OR and N_ALT of the first run are gone
+        let ids: Vec<&str> = info
+            .split(';')
+            .map(|kv| kv.split('=').next().unwrap())
+            .collect();
+        assert_eq!(ids, ["AC_A", "AC_B"], "{info}");
+    }
+}
+
+#[test]
+fn include_total_replaces_input_totals() {
+    let header = "##INFO=<ID=AC,Number=A,Type=Integer,Description=\"Allele count\">\n\
+                  ##INFO=<ID=AN,Number=1,Type=Integer,Description=\"Allele number\">\n";
+    let vcf = common::vcf(2, &["chr1\t100\t.\tA\tC\t.\tPASS\tAC=7;AN=9\tGT\t0/1\t0/0"]).replacen(
+        "##FORMAT",
+        &format!("{header}##FORMAT"),
+        1,
+    );
+    let args = ["--tags", "AC,AN", "--include-total"];
+    let out = common::annotate(&vcf, "S1\tA\nS2\tA\n", &args);
+    // without --force, the input AC and AN of the cohort are recomputed
+    let fields = common::info_fields(common::info_columns(&out)[0]);
+    assert_eq!((fields["AC"], fields["AN"]), ("1", "4"));
+    assert_eq!(out.matches("##INFO=<ID=AC,").count(), 1);
+
+    let args = ["--tags", "AC,AN", "--include-total", "--skip-existing"];
+    let out = common::annotate(&vcf, "S1\tA\nS2\tA\n", &args);
+    let fields = common::info_fields(common::info_columns(&out)[0]);
+    assert_eq!((fields["AC"], fields["AN"]), ("7", "9"));
+}
+
+#[test]
+fn reannotate_same_groups() {
+    let labels = common::fixture("triallelic_groups");
+    let path = common::temp_path("reannotate.vcf");
+    let args = ["-o", path.to_str().unwrap()];
+    let out = common::run(&common::fixture("triallelic.vcf"), &labels, &args);
+    assert!(out.status.success());
+    let second = common::run(&path, &labels, &["-o", "-", "--force"]);
+    let first = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+    assert!(second.status.success());
+    let second = String::from_utf8(second.stdout).unwrap();
+
+    // the old group fields are replaced in place, not appended again
+    assert_eq!(common::info_columns(&second), common::info_columns(&first));
+    let records = |vcf: &str| -> Vec<String> {
+        vcf.lines()
+            .filter(|line| !line.starts_with("##"))
+            .map(str::to_string)
+            .collect()
+    };
+    assert_eq!(records(&second), records(&first));
+}
+
+#[test]
+fn deprecated_strict_still_accepted() {
+    let input = common::fixture("triallelic.vcf");
+    for flag in ["--force", "--skip-existing"] {
+        let out = vcfgrpaf(&input, "S1\tEUR\n", &["--strict", flag]);
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(out.status.success(), "{flag}: {stderr}");
+        assert!(stderr.contains("--strict is deprecated"), "{stderr}");
+    }
+}
@@ -0,0 +1,30 @@
+// tests/singletons.rs
+//! N_SINGLETON/N_DOUBLETON count, per ALT, the alleles seen exactly once or twice in a group
+mod common;
+
+#[test]
+fn singleton_and_doubleton_counts() {
+    let vcf = common::vcf(
+        4,
+        &[
+            "chr1\t100\t.\tA\tC\t.\tPASS\t.\tGT\t0/1\t0/0\t0/0\t0/0",
+            "chr1\t200\t.\tA\tC,G\t.\tPASS\t.\tGT\t0/1\t0/2\t2/2\t0/0",
+        ],
+    );
+    let out = common::annotate(
+        &vcf,
+        "S1\tA\nS2\tA\nS3\tB\nS4\tB\n",
+        &["--tags", "N_SINGLETON,N_DOUBLETON"],
+    );
+    let info = common::info_columns(&out);
+    // S1 carries the only C
+    assert_eq!(
+        info[0],
+        "N_SINGLETON_A=1;N_DOUBLETON_A=0;N_SINGLETON_B=0;N_DOUBLETON_B=0"
+    );
+    // one C and one G in A, the G of S3 twice in B
+    assert_eq!(
+        info[1],
+        "N_SINGLETON_A=1,1;N_DOUBLETON_A=0,0;N_SINGLETON_B=0,0;N_DOUBLETON_B=0,1"
+    );
+}
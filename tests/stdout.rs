@@ -0,0 +1,27 @@
+// tests/stdout.rs
+//! With `-o -` stdout carries the VCF alone; the progress messages go to stderr
+mod common;
+use std::process::Command;
+
+#[test]
+fn stdout_is_the_vcf() {
+    // not common::run, which adds --quiet
+    let out = Command::new(env!("CARGO_BIN_EXE_vcfgrpaf"))
+        .arg(common::fixture("triallelic.vcf"))
+        .arg("-l")
+        .arg(common::fixture("triallelic_groups"))
+        .args(["-o", "-"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.starts_with("##fileformat=VCF"), "{stdout}");
+    // the #CHROM line and the record, with their 9 fixed and 6 sample columns
+    let lines: Vec<&str> = stdout.lines().filter(|l| !l.starts_with("##")).collect();
+    assert_eq!(lines.len(), 2);
+    for line in lines {
+        assert_eq!(line.split('\t').count(), 15, "{line}");
+    }
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("Finished vcfgrpaf"), "{stderr}");
+}
@@ -0,0 +1,22 @@
+// tests/triallelic.rs
+//! Per-ALT statistics at a site with two ALT alleles: 0/1, 0/2 and 1/2 in group A, and
+//! 2/2, 0/0 and ./. in group B
+mod common;
+
+#[test]
+fn per_alt_allele_counts() {
+    let out = common::annotate_fixture("triallelic", &["--tags", "AC,AN"]);
+    let info = common::info_fields(common::info_columns(&out)[0]);
+    assert_eq!((info["AC_A"], info["AN_A"]), ("2,2", "6"));
+    assert_eq!((info["AC_B"], info["AN_B"]), ("0,2", "4"));
+}
+
+#[test]
+fn per_alt_het_hom_counts() {
+    let out = common::annotate_fixture("triallelic", &["--tags", "N_HET,N_HOMALT"]);
+    let info = common::info_fields(common::info_columns(&out)[0]);
+    // each ALT of A is in two hets, and 1/2 counts for both
+    assert_eq!((info["N_HET_A"], info["N_HOMALT_A"]), ("2,2", "0,0"));
+    // 2/2 is homozygous for ALT 2 only
+    assert_eq!((info["N_HET_B"], info["N_HOMALT_B"]), ("0,0", "0,1"));
+}
@@ -0,0 +1,120 @@
+// tests/truvari.rs
+//! Runs vcfgrpaf on a small VCF and compares its INFO fields with truvari grpaf.py on the
+//! same input. The truvari half is skipped when `truvari` isn't on the PATH.
+mod common;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+const FIXTURE: &str = "truvari.vcf";
+const LABELS: &str = "truvari_groups";
+/// Statistics both tools write
+const TAGS: &str = "AF,MAF,MAC,AC,AN,N_HEMI,N_MISS,N_HOMREF,N_HET,N_HOMALT,HWE,ExcHet";
+const GROUPS: [&str; 2] = ["A", "B"];
+
+/// INFO `key=value` pairs of every record, keyed by `CHROM:POS`
+fn info_fields(vcf: &Path) -> HashMap<String, HashMap<String, String>> {
+    let text = std::fs::read_to_string(vcf).unwrap();
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| {
+            let cols: Vec<&str> = line.split('\t').collect();
+            let info = cols[7]
+                .split(';')
+                .map(|kv| match kv.split_once('=') {
+                    Some((k, v)) => (k.to_string(), v.to_string()),
+                    None => (kv.to_string(), String::new()),
+                })
+                .collect();
+            (format!("{}:{}", cols[0], cols[1]), info)
+        })
+        .collect()
+}
+
+fn run_vcfgrpaf(out: &Path) -> HashMap<String, HashMap<String, String>> {
+    let args = ["-o", out.to_str().unwrap(), "--tags", TAGS];
+    let status = common::run(&common::fixture(FIXTURE), &common::fixture(LABELS), &args).status;
+    assert!(status.success());
+    info_fields(out)
+}
+
+/// Values equal as strings, or as numbers within a float tolerance
+fn same_value(a: &str, b: &str) -> bool {
+    let (a, b): (Vec<&str>, Vec<&str>) = (a.split(',').collect(), b.split(',').collect());
+    a.len() == b.len()
+        && a.iter().zip(&b).all(|(x, y)| {
+            x == y
+                || match (x.parse::<f64>(), y.parse::<f64>()) {
+                    (Ok(x), Ok(y)) => (x - y).abs() <= 1e-4 * y.abs().max(1.0),
+                    _ => false,
+                }
+        })
+}
+
+#[test]
+fn counts_by_hand() {
+    let out = common::temp_path("hand.vcf");
+    let info = run_vcfgrpaf(&out);
+    std::fs::remove_file(&out).ok();
+
+    let rec = &info["chr1:100"];
+    for (key, value) in [
+        ("AC_A", "3"),
+        ("AN_A", "6"),
+        ("N_HET_A", "1"),
+        ("AC_B", "1"),
+        ("AN_B", "4"),
+        ("N_MISS_B", "1"),
+    ] {
+        assert_eq!(rec[key], value, "chr1:100 {key}");
+    }
+    assert_eq!(info["chr1:200"]["N_HOMALT_B"], "2");
+    // haploid calls count one allele and go to N_HEMI
+    let rec = &info["chr1:300"];
+    assert_eq!((&*rec["AN_A"], &*rec["N_HEMI_A"]), ("3", "1"));
+    assert_eq!((&*rec["AN_B"], &*rec["N_HEMI_B"]), ("5", "1"));
+}
+
+#[test]
+fn matches_truvari() {
+    if Command::new("truvari").arg("version").output().is_err() {
+        eprintln!("truvari is not installed, skipping the comparison");
+        return;
+    }
+    let ours = common::temp_path("ours.vcf");
+    let theirs = common::temp_path("truvari.vcf");
+    let info = run_vcfgrpaf(&ours);
+    let status = Command::new("truvari")
+        .args(["anno", "grpaf", "-l"])
+        .arg(common::fixture(LABELS))
+        .args(["-t", TAGS, "-o"])
+        .arg(&theirs)
+        .arg(common::fixture(FIXTURE))
+        .status()
+        .unwrap();
+    assert!(status.success(), "truvari anno grpaf failed");
+    let expected = info_fields(&theirs);
+    std::fs::remove_file(&ours).ok();
+    std::fs::remove_file(&theirs).ok();
+
+    let mut diffs = Vec::new();
+    for (site, want) in &expected {
+        for tag in TAGS.split(',') {
+            for grp in GROUPS {
+                let key = format!("{tag}_{grp}");
+                let Some(want) = want.get(&key) else {
+                    continue;
+                };
+                match info.get(site).and_then(|got| got.get(&key)) {
+                    Some(got) if same_value(got, want) => {}
+                    got => diffs.push(format!("{site} {key}: vcfgrpaf {got:?}, truvari {want}")),
+                }
+            }
+        }
+    }
+    assert!(
+        diffs.is_empty(),
+        "differences from truvari:\n{}",
+        diffs.join("\n")
+    );
+}